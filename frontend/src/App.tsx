@@ -8,6 +8,7 @@ import {
   Grid,
   Form,
   Segment,
+  Button,
 } from "semantic-ui-react";
 import dayjs from "dayjs";
 import relativeTime from "dayjs/plugin/relativeTime";
@@ -24,19 +25,103 @@ const WebsocketURI =
 
 const API_URI = process.env.REACT_APP_API_URL;
 
+// where the API token is kept, the server asks for one when read_token or write_token is set
+const TOKEN_KEY = "yodel-token";
+
+// fetch from the API with the stored token, asking for a token when the server refuses
+const apiFetch = (path: string, init: RequestInit = {}): Promise<Response> => {
+  const token = localStorage.getItem(TOKEN_KEY);
+  const headers = new Headers(init.headers);
+  if (token) {
+    headers.set("Authorization", `Bearer ${token}`);
+  }
+
+  return fetch(`${API_URI}${path}`, { ...init, headers }).then((resp) => {
+    if (resp.status !== 401) {
+      return resp;
+    }
+
+    // another request might have asked for the token in the meantime
+    const stored = localStorage.getItem(TOKEN_KEY);
+    const entered =
+      stored !== token ? stored : window.prompt("The server needs a token");
+    if (!entered) {
+      return resp;
+    }
+    localStorage.setItem(TOKEN_KEY, entered);
+    return apiFetch(path, init);
+  });
+};
+
+// browsers can't set headers on websockets, the token goes in the query instead
+const websocketURI = (): string => {
+  const token = localStorage.getItem(TOKEN_KEY);
+  return token
+    ? `${WebsocketURI}?token=${encodeURIComponent(token)}`
+    : WebsocketURI;
+};
+
 interface Location {
   name: string;
   path: string;
+  filesystemType: "local" | "network" | "unknown";
+}
+
+interface LocationInfo {
+  path: string;
+  filesystemType: "local" | "network" | "unknown";
 }
 
 interface Job {
+  id: string;
   url: Url;
   title?: String;
   location: Location;
   startedOn: Date;
-  status: "Finished" | "InProgress" | { ["Failed"]: string };
+  status:
+    | "Finished"
+    | "InProgress"
+    | "Queued"
+    | "AwaitingReview"
+    | "Paused"
+    | { ["Failed"]: string };
 }
 
+// queued, running and paused jobs are pending, all others are completed
+const isPending = (job: Job): boolean =>
+  job.status === "Queued" ||
+  job.status === "InProgress" ||
+  job.status === "Paused";
+
+// pause a running job or resume a paused one, the job list is updated over the websocket
+const setPaused = (job: Job, paused: boolean) => {
+  const action = paused ? "pause" : "resume";
+  apiFetch(`/jobs/${job.id}/${action}`, { method: "POST" })
+    .then((resp) => {
+      if (resp.ok) {
+        return;
+      }
+
+      resp.json().then((res) => {
+        toast({
+          type: "error",
+          title: `Unable to ${action} the job`,
+          description: res,
+          time: 0,
+        });
+      });
+    })
+    .catch((err) => {
+      console.error(err);
+    });
+};
+
+// replace the job in the list, or add it when it's not in the list yet
+const upsert = (jobs: Job[], job: Job): Job[] =>
+  jobs.some((other) => other.id === job.id)
+    ? jobs.map((other) => (other.id === job.id ? job : other))
+    : [...jobs, job];
+
 const JobList = ({
   jobs,
   isCompleted,
@@ -45,12 +130,10 @@ const JobList = ({
   isCompleted: boolean;
 }) => {
   const jobStatus = (job: Job): string => {
-    if (job.status === "Finished" || job.status === "InProgress") {
-      return "Finished";
+    if (typeof job.status === "string") {
+      return job.status;
     }
 
-    console.log(job.status);
-
     return `Failed: ${job.status.Failed}`;
   };
 
@@ -61,6 +144,7 @@ const JobList = ({
           <Table.HeaderCell>Video</Table.HeaderCell>
           <Table.HeaderCell>Started On</Table.HeaderCell>
           {isCompleted && <Table.HeaderCell>Status</Table.HeaderCell>}
+          {!isCompleted && <Table.HeaderCell />}
         </Table.Row>
       </Table.Header>
 
@@ -73,6 +157,20 @@ const JobList = ({
               </Table.Cell>
               <Table.Cell>{dayjs(job.startedOn).fromNow()}</Table.Cell>
               {isCompleted && <Table.Cell>{jobStatus(job)}</Table.Cell>}
+              {!isCompleted && (
+                <Table.Cell collapsing>
+                  {job.status === "InProgress" && (
+                    <Button size="mini" onClick={() => setPaused(job, true)}>
+                      Pause
+                    </Button>
+                  )}
+                  {job.status === "Paused" && (
+                    <Button size="mini" onClick={() => setPaused(job, false)}>
+                      Resume
+                    </Button>
+                  )}
+                </Table.Cell>
+              )}
             </Table.Row>
           );
         })}
@@ -90,7 +188,7 @@ const CreateJobForm = ({ locations }: { locations: Location[] }) => {
 
   const handleSubmit = () => {
     setLoading(true);
-    fetch(`${API_URI}/jobs`, {
+    apiFetch("/jobs", {
       method: "POST",
       headers: {
         "Content-Type": "application/json",
@@ -170,7 +268,7 @@ function App() {
   const [connected, setConnected] = React.useState(false);
 
   React.useEffect(() => {
-    fetch(`${API_URI}/jobs`)
+    apiFetch("/jobs")
       .then((resp) => resp.json() as Promise<Job[]>)
       .then((data) => {
         setPendingJobs(data);
@@ -181,7 +279,7 @@ function App() {
   }, []);
 
   React.useEffect(() => {
-    fetch(`${API_URI}/completed-jobs`)
+    apiFetch("/completed-jobs")
       .then((resp) => resp.json() as Promise<Job[]>)
       .then((data) => {
         setCompletedJobs(data);
@@ -192,15 +290,15 @@ function App() {
   }, []);
 
   React.useEffect(() => {
-    fetch(`${API_URI}/locations`)
-      .then((resp) => resp.json() as Promise<Object>)
+    apiFetch("/locations")
+      .then((resp) => resp.json() as Promise<Record<string, LocationInfo>>)
       .then((data) => {
         let res: Location[] = [];
 
-        for (let [name, path] of Object.entries(data)) {
+        for (let [name, info] of Object.entries(data)) {
           res.push({
             name,
-            path,
+            ...info,
           });
         }
 
@@ -212,38 +310,86 @@ function App() {
   }, []);
 
   React.useEffect(() => {
-    const socket = new ReconnectingWebSocket(WebsocketURI);
+    // the url is built again on every reconnect, to pick up a token that was entered since
+    const socket = new ReconnectingWebSocket(websocketURI);
+    // sequence number of the latest broadcast, older broadcasts are stale
+    let lastSeq = 0;
+    // the server that sent the sequence numbers, it changes when the server restarts
+    let instanceId: string | undefined;
 
     socket.onmessage = (update) => {
       const message = JSON.parse(update.data);
 
       console.log(message);
 
-      if (message.PendingJobs) {
-        setPendingJobs(message.PendingJobs);
+      if (message.type === "hello") {
+        if (message.instanceId !== instanceId) {
+          // a restarted server starts counting from 0 and forgot the old jobs,
+          // the jobs sent after the hello replace everything
+          instanceId = message.instanceId;
+          lastSeq = 0;
+          setPendingJobs([]);
+          setCompletedJobs([]);
+        }
+        return;
       }
 
-      if (message.CompletedJobs) {
-        setCompletedJobs(message.CompletedJobs);
+      if (message.type === "snapshot") {
+        // a snapshot replaces everything, the broadcasts after it build on it
+        if (message.seq !== undefined) {
+          lastSeq = message.seq;
+        }
+        setPendingJobs(message.data.pending);
+        setCompletedJobs(message.data.completed);
+        return;
       }
 
-      if (message.Finished) {
-        toast({
-          type: "success",
-          title: "Job complete!",
-          description: message.Finished.url,
-          time: 5000,
-        });
+      if (message.seq !== undefined) {
+        if (message.seq <= lastSeq) {
+          return;
+        }
+        lastSeq = message.seq;
       }
 
-      if (message.Failed) {
-        console.error(message.Failed);
-        toast({
-          type: "error",
-          title: "Job failed!",
-          description: message.Failed.reason,
-          time: 0,
-        });
+      switch (message.type) {
+        case "jobUpdated": {
+          const job: Job = message.data;
+          const others = (jobs: Job[]) =>
+            jobs.filter((other) => other.id !== job.id);
+          if (isPending(job)) {
+            setPendingJobs((jobs) => upsert(jobs, job));
+            setCompletedJobs(others);
+          } else {
+            setCompletedJobs((jobs) => upsert(jobs, job));
+            setPendingJobs(others);
+          }
+          break;
+        }
+        case "finished":
+          toast({
+            type: "success",
+            title: "Job complete!",
+            description: message.data.url,
+            time: 5000,
+          });
+          break;
+        case "warning":
+          toast({
+            type: "warning",
+            title: "Job warning",
+            description: message.data.message,
+            time: 10000,
+          });
+          break;
+        case "failed":
+          console.error(message.data);
+          toast({
+            type: "error",
+            title: "Job failed!",
+            description: message.data.reason,
+            time: 0,
+          });
+          break;
       }
     };
 
@@ -0,0 +1,229 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::process::Output;
+
+use actix::prelude::*;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use futures::channel::oneshot;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::auth;
+use crate::config::CONFIG;
+use crate::errors::YodelError;
+use crate::jobs::JobServer;
+use crate::version;
+use crate::LOG_FILE;
+
+/// Number of log lines returned when the request doesn't say how many
+const DEFAULT_LOG_LINES: usize = 100;
+/// Maximum number of log lines that can be requested at once
+const MAX_LOG_LINES: usize = 1000;
+
+/// Fails unless the request has the configured admin token as its bearer token
+/// Admin endpoints are unavailable when no admin token is configured
+pub(crate) fn authorize(req: &HttpRequest) -> Result<(), YodelError> {
+    let token = CONFIG
+        .admin_token
+        .as_deref()
+        .ok_or(YodelError::Unauthorized)?;
+
+    let authorized =
+        auth::bearer_token(req.headers()).is_some_and(|bearer| auth::tokens_match(bearer, token));
+
+    if authorized {
+        Ok(())
+    } else {
+        Err(YodelError::Unauthorized)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct LogsQuery {
+    lines: Option<usize>,
+}
+
+/// The last lines of the server log
+#[get("/admin/logs")]
+async fn logs(req: HttpRequest, query: web::Query<LogsQuery>) -> Result<HttpResponse, YodelError> {
+    authorize(&req)?;
+
+    let lines = query.lines.unwrap_or(DEFAULT_LOG_LINES).min(MAX_LOG_LINES);
+    let lines = web::block(move || tail(Path::new(LOG_FILE), lines)).await?;
+
+    Ok(HttpResponse::Ok().json(lines))
+}
+
+/// Stop accepting and starting jobs, the running jobs are finished
+#[derive(Message)]
+#[rtype(result = "DrainStatus")]
+pub(crate) struct Drain;
+
+#[derive(Message)]
+#[rtype(result = "DrainStatus")]
+pub(crate) struct GetDrainStatus;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DrainStatus {
+    pub(crate) draining: bool,
+    pub(crate) in_progress: usize,
+    /// Queued jobs stay queued until the server is restarted
+    pub(crate) queued: usize,
+    /// True once the server drains and no jobs are running anymore
+    pub(crate) idle: bool,
+}
+
+/// Drain the server before shutting it down
+#[post("/admin/drain")]
+async fn drain(
+    req: HttpRequest,
+    job_server: web::Data<Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    authorize(&req)?;
+
+    let status = job_server.send(Drain).await?;
+    Ok(HttpResponse::Ok().json(status))
+}
+
+/// Whether the server drains, and how many jobs are still running
+#[get("/admin/drain/status")]
+async fn drain_status(
+    req: HttpRequest,
+    job_server: web::Data<Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    authorize(&req)?;
+
+    let status = job_server.send(GetDrainStatus).await?;
+    Ok(HttpResponse::Ok().json(status))
+}
+
+/// The youtube-dl processes of the running jobs
+#[derive(Message)]
+#[rtype(result = "Vec<Process>")]
+pub(crate) struct GetProcesses;
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Process {
+    /// Id of the job
+    pub(crate) id: Uuid,
+    pub(crate) pid: u32,
+}
+
+/// The process ids of the running downloads, for debugging on the host
+#[get("/admin/processes")]
+async fn processes(
+    req: HttpRequest,
+    job_server: web::Data<Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    authorize(&req)?;
+
+    let processes = job_server.send(GetProcesses).await?;
+    Ok(HttpResponse::Ok().json(processes))
+}
+
+/// Update the downloader, unless downloads are running
+/// No jobs are started until the update finished
+#[derive(Message)]
+#[rtype(result = "Result<oneshot::Receiver<UpdateResult>, YodelError>")]
+pub(crate) struct UpdateDownloader;
+
+/// Sent by the update thread when the update finished
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct UpdateFinished;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UpdateResult {
+    success: bool,
+    /// Output of the update command
+    output: String,
+    /// Version of the downloader after the update
+    version: Option<String>,
+}
+
+/// Update the downloader with the configured update method
+/// Fails with 503 while downloads are running, they'd have their binary replaced
+#[post("/admin/update-downloader")]
+async fn update_downloader(
+    req: HttpRequest,
+    job_server: web::Data<Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    authorize(&req)?;
+
+    let receiver = job_server.send(UpdateDownloader).await??;
+    let result = receiver
+        .await
+        .map_err(|_| YodelError::InternalServerError)?;
+    Ok(HttpResponse::Ok().json(result))
+}
+
+/// Run the update command and detect the new version, this blocks until the update finished
+pub(crate) fn update() -> UpdateResult {
+    info!("updating {}", CONFIG.downloader.display());
+    let result = match CONFIG.update_command().output() {
+        Ok(Output {
+            status,
+            stdout,
+            stderr,
+        }) => UpdateResult {
+            success: status.success(),
+            output: format!(
+                "{}{}",
+                String::from_utf8_lossy(&stdout),
+                String::from_utf8_lossy(&stderr)
+            ),
+            version: version::refresh_downloader(),
+        },
+        Err(e) => UpdateResult {
+            success: false,
+            output: format!("unable to run the update: {}", e),
+            version: version::refresh_downloader(),
+        },
+    };
+
+    if result.success {
+        info!(
+            "updated {} to {}",
+            CONFIG.downloader.display(),
+            result.version.as_deref().unwrap_or("an unknown version")
+        );
+    } else {
+        error!(
+            "updating {} failed: {}",
+            CONFIG.downloader.display(),
+            result.output.trim()
+        );
+    }
+    result
+}
+
+/// Read the last `lines` lines of a file
+/// The file is read backwards in blocks, so only its end is loaded
+fn tail(path: &Path, lines: usize) -> std::io::Result<Vec<String>> {
+    const BLOCK_SIZE: u64 = 8192;
+
+    let mut file = File::open(path)?;
+    let mut position = file.metadata()?.len();
+    let mut buffer = Vec::new();
+
+    // the file ends with a newline, so one more newline than lines is needed
+    while position > 0 && buffer.iter().filter(|&&byte| byte == b'\n').count() <= lines {
+        let size = BLOCK_SIZE.min(position);
+        position -= size;
+
+        let mut block = vec![0; size as usize];
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut block)?;
+        block.extend_from_slice(&buffer);
+        buffer = block;
+    }
+
+    let text = String::from_utf8_lossy(&buffer);
+    let all: Vec<&str> = text.lines().collect();
+    let skip = all.len().saturating_sub(lines);
+
+    Ok(all[skip..].iter().map(|line| line.to_string()).collect())
+}
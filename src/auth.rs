@@ -0,0 +1,133 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::http::{header, HeaderMap, Method};
+use actix_web::web;
+use serde::Deserialize;
+
+use crate::config::CONFIG;
+use crate::errors::YodelError;
+
+/// What a request is allowed to do, a token that allows writing also allows reading
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Access {
+    None,
+    Read,
+    Write,
+}
+
+/// Browsers can't set headers on websocket requests, they pass the token in the query instead
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+/// The bearer token of the request's Authorization header
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Fails unless the request's token allows it, see `Config::read_token` and `Config::write_token`
+/// Reading is done with GET requests, everything else needs the write token
+/// Everything is allowed when no tokens are configured
+pub(crate) fn check(req: &ServiceRequest) -> Result<(), YodelError> {
+    if CONFIG.read_token.is_none() && CONFIG.write_token.is_none() {
+        return Ok(());
+    }
+
+    // the admin endpoints check the admin token themselves, health probes don't send tokens
+    let path = req.path();
+    if path.starts_with("/api/admin/") || path == "/api/healthz" || path == "/api/readyz" {
+        return Ok(());
+    }
+
+    // the query ends up in logs and browser histories, only websockets can't do without it
+    let token = match bearer_token(req.headers()) {
+        Some(token) => Some(token.to_string()),
+        None if is_websocket(path) => web::Query::<TokenQuery>::from_query(req.query_string())
+            .ok()
+            .and_then(|query| query.into_inner().token),
+        None => None,
+    };
+    let granted = token.as_deref().map_or(Access::None, access);
+
+    if granted >= required(req) {
+        Ok(())
+    } else {
+        Err(YodelError::Unauthorized)
+    }
+}
+
+/// Returns true for the paths of the websockets, see `websocket::route`
+fn is_websocket(path: &str) -> bool {
+    path == "/ws" || (path.starts_with("/api/") && path.ends_with("/ws"))
+}
+
+/// Compare a token to the expected one, the time this takes doesn't depend on
+/// how much of the token is right
+pub(crate) fn tokens_match(token: &str, expected: &str) -> bool {
+    let (token, expected) = (token.as_bytes(), expected.as_bytes());
+    token.len() == expected.len()
+        && token
+            .iter()
+            .zip(expected)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// The access the token grants, a token that's configured on its own grants everything
+fn access(token: &str) -> Access {
+    let matches = |expected: &Option<String>| {
+        expected
+            .as_deref()
+            .is_some_and(|expected| tokens_match(token, expected))
+    };
+
+    if matches(&CONFIG.write_token) {
+        Access::Write
+    } else if matches(&CONFIG.read_token) {
+        if CONFIG.write_token.is_none() {
+            Access::Write
+        } else {
+            Access::Read
+        }
+    } else {
+        Access::None
+    }
+}
+
+fn required(req: &ServiceRequest) -> Access {
+    // quick add is a GET request that adds a job
+    if req.path() == "/api/quick-add" {
+        return Access::Write;
+    }
+
+    match *req.method() {
+        Method::GET | Method::HEAD | Method::OPTIONS => Access::Read,
+        _ => Access::Write,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_match_only_the_same_token() {
+        assert!(tokens_match("change-me", "change-me"));
+        assert!(!tokens_match("change-mE", "change-me"));
+        assert!(!tokens_match("change", "change-me"));
+        assert!(!tokens_match("", "change-me"));
+    }
+
+    #[test]
+    fn only_websockets_take_the_token_from_the_query() {
+        assert!(is_websocket("/ws"));
+        assert!(is_websocket(
+            "/api/jobs/0f4e8a4e-64e4-4e5b-8f55-33c2a8e0b7c1/ws"
+        ));
+        assert!(!is_websocket("/api/jobs"));
+        assert!(!is_websocket("/api/quick-add"));
+    }
+}
@@ -0,0 +1,61 @@
+use serde::ser::Error;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::Value;
+
+use crate::config::CONFIG;
+
+/// How the keys of the jobs in the API's json are written
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Casing {
+    /// eg: startedOn
+    #[default]
+    CamelCase,
+    /// eg: started_on
+    SnakeCase,
+}
+
+/// Serializes the value with the configured casing
+/// The types are serialized in camelCase, so only snake_case needs their keys rewritten,
+/// values like the message types are left as they are
+#[derive(Debug)]
+pub(crate) struct Cased<T>(pub(crate) T);
+
+impl<T: Serialize> Serialize for Cased<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match CONFIG.json_casing {
+            Casing::CamelCase => self.0.serialize(serializer),
+            Casing::SnakeCase => {
+                let value = serde_json::to_value(&self.0).map_err(S::Error::custom)?;
+                snake_case_keys(value).serialize(serializer)
+            }
+        }
+    }
+}
+
+fn snake_case_keys(value: Value) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .map(|(key, value)| (snake_case(&key), snake_case_keys(value)))
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.into_iter().map(snake_case_keys).collect()),
+        value => value,
+    }
+}
+
+/// Turn a camelCase name into snake_case, eg: writeDescription into write_description
+pub(crate) fn snake_case(name: &str) -> String {
+    let mut snake = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            snake.push('_');
+            snake.push(c.to_ascii_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
@@ -1,21 +1,792 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
-use actix_web::{get, HttpResponse, Responder};
-use serde::{Deserialize, Serialize};
+use actix_web::{get, web, HttpResponse};
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::casing::Casing;
+use crate::desktop::DesktopNotification;
+use crate::errors::YodelError;
+use crate::formats;
+use crate::jobs::{self, SubtitleMode};
+use crate::repository::Storage;
+use crate::telegram::Telegram;
+use crate::webhook;
 
 lazy_static::lazy_static! {
-    static ref LOCATIONS: HashMap<String, PathBuf> = {
+    pub(crate) static ref CONFIG: Config = {
         let contents = std::fs::read_to_string("config.yaml").unwrap();
-        let items: HashMap<String, PathBuf> = serde_yaml::from_str(&contents).unwrap();
+        let mut config = match Config::parse(&contents) {
+            Ok(config) => config,
+            Err(e) => panic!("invalid config.yaml: {}", e),
+        };
+        config.resolve_locations();
+        if let Err(e) = config.validate() {
+            panic!("invalid config.yaml: {}", e);
+        }
 
-        items
+        config
     };
+
+    /// Filesystem type of each location, detected on first use
+    static ref FILESYSTEM_TYPES: HashMap<String, FilesystemType> = CONFIG
+        .locations
+        .iter()
+        .map(|(name, location)| (name.clone(), filesystem_type(location.path())))
+        .collect();
+
+    /// Program and arguments that start the downloader with a lower priority
+    static ref PRIORITY_WRAPPER: Vec<String> = priority_wrapper();
+}
+
+/// Filesystem names from /proc/mounts that are considered network filesystems
+#[cfg(target_os = "linux")]
+const NETWORK_FILESYSTEMS: &[&str] = &[
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "ncpfs",
+    "afs",
+    "9p",
+    "ceph",
+    "glusterfs",
+    "davfs",
+    "fuse.sshfs",
+    "fuse.glusterfs",
+    "fuse.rclone",
+];
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Config {
+    locations: HashMap<String, LocationConfig>,
+    /// Relative location paths are resolved against this directory
+    /// They're rejected without it, they'd depend on the directory yodel was started in
+    base_dir: Option<PathBuf>,
+    /// Location used for jobs that don't specify one
+    pub(crate) default_location: Option<String>,
+    /// The youtube-dl compatible program used for downloads, eg: youtube-dl or yt-dlp
+    #[serde(default = "default_downloader")]
+    pub(crate) downloader: PathBuf,
+    /// Default number of youtube-dl retries, for both the download and its fragments
+    /// youtube-dl's own default is used when this is not set
+    pub(crate) retries: Option<u32>,
+    /// Directory with the frontend build
+    /// Defaults to frontend/build, or frontend on FreeBSD
+    pub(crate) frontend_dir: Option<PathBuf>,
+    /// Only serve the API, without the frontend
+    #[serde(default)]
+    pub(crate) api_only: bool,
+    /// Allow adding jobs with a GET request, see `jobs::quick_add`
+    #[serde(default)]
+    pub(crate) quick_add: bool,
+    /// Embed chapter markers by default, yt-dlp only
+    #[serde(default)]
+    pub(crate) embed_chapters: bool,
+    /// Look up the video title of new jobs, jobs are shown by their url without one
+    #[serde(default = "default_fetch_titles")]
+    pub(crate) fetch_titles: bool,
+    /// Number of looked up titles to remember, 0 disables the cache
+    #[serde(default = "default_title_cache_size")]
+    pub(crate) title_cache_size: usize,
+    /// Seconds after which a remembered title is looked up again
+    #[serde(default = "default_title_cache_ttl")]
+    pub(crate) title_cache_ttl: u64,
+    /// File where youtube-dl records the downloaded videos, they're skipped when downloaded again
+    pub(crate) download_archive: Option<PathBuf>,
+    /// Periodically update the downloader, see `admin::update_downloader`
+    #[serde(default)]
+    pub(crate) auto_update: bool,
+    /// Hours between two automatic updates of the downloader
+    #[serde(default = "default_auto_update_interval")]
+    pub(crate) auto_update_interval: u64,
+    /// How the downloader updates itself
+    #[serde(default)]
+    pub(crate) update_method: UpdateMethod,
+    /// Directory with youtube-dl options files, jobs can pick one with `config_file`
+    pub(crate) config_files_dir: Option<PathBuf>,
+    /// Highest video resolution that's downloaded, in pixels, it's applied on top of a job's `format_id`
+    pub(crate) max_height: Option<u32>,
+    /// Stop downloading a playlist after this many videos
+    pub(crate) max_playlist_items: Option<u32>,
+    /// Environment variables of the downloader, eg: HTTP_PROXY
+    #[serde(default)]
+    pub(crate) environment: BTreeMap<String, Secret>,
+    /// Casing of the keys of the jobs in API responses, websocket messages and webhooks
+    #[serde(default)]
+    pub(crate) json_casing: Casing,
+    /// Remove the partial files of failed downloads
+    #[serde(default)]
+    pub(crate) cleanup_on_failure: bool,
+    /// Create the directory of a location when a job is added for it and it doesn't exist,
+    /// instead of rejecting the job
+    #[serde(default)]
+    pub(crate) create_missing_dirs: bool,
+    /// Compute the SHA-256 of every download, not only when a job expects one
+    #[serde(default)]
+    pub(crate) hash_downloads: bool,
+    /// Only use ASCII characters without spaces in file names by default
+    #[serde(default)]
+    pub(crate) restrict_filenames: bool,
+    /// Prefer free formats, like webm, by default
+    #[serde(default)]
+    pub(crate) prefer_free_formats: bool,
+    /// Default container of videos merged from separate video and audio streams
+    pub(crate) merge_format: Option<String>,
+    /// Directory where state is kept across restarts
+    pub(crate) data_dir: Option<PathBuf>,
+    /// How the jobs are kept in the data directory
+    #[serde(default)]
+    pub(crate) storage: Storage,
+    /// When set, jobs are downloaded here first
+    /// and only moved to their location once they're approved
+    pub(crate) staging: Option<PathBuf>,
+    /// Jobs aren't started when their filesystem has less free space than this
+    pub(crate) min_free_space: Option<FreeSpace>,
+    /// Only urls from these domains, or their subdomains, can be downloaded
+    /// An empty list allows all domains
+    #[serde(default)]
+    pub(crate) allowed_domains: Vec<String>,
+    /// Urls from these domains, or their subdomains, can never be downloaded
+    /// This takes precedence over `allowed_domains`
+    #[serde(default)]
+    pub(crate) blocked_domains: Vec<String>,
+    /// Move a queued job to the front of the queue when it's submitted again,
+    /// instead of failing with a conflict
+    #[serde(default)]
+    pub(crate) bump_duplicates: bool,
+    /// Derive job ids from the url, location and options instead of picking random ones,
+    /// see `jobs::content_id`
+    #[serde(default)]
+    pub(crate) deterministic_ids: bool,
+    /// Pick the location of jobs that don't specify one, the first matching rule wins
+    #[serde(default)]
+    pub(crate) routing: Vec<RoutingRule>,
+    /// Options for the downloads from specific hosts, the first matching host wins
+    #[serde(default)]
+    pub(crate) sources: Vec<Source>,
+    /// Job options clients are allowed to set, eg: format_id
+    /// All options are allowed when this is not set
+    pub(crate) allowed_options: Option<Vec<String>>,
+    /// Minimum number of seconds between starting two downloads from the same host
+    #[serde(default)]
+    pub(crate) host_cooldown: u64,
+    /// Maximum number of open connections per worker thread
+    #[serde(default = "default_max_connections")]
+    pub(crate) max_connections: usize,
+    /// Maximum number of connections per worker thread that can be accepted at the same time
+    #[serde(default = "default_max_connection_rate")]
+    pub(crate) max_connection_rate: usize,
+    /// Seconds after which a download is stopped and fails, no matter how far along it is
+    /// Jobs can ask for a shorter limit, but not for a longer one
+    /// Live recordings aren't limited, they can set a limit of their own
+    pub(crate) max_duration: Option<u64>,
+    /// Minimum number of milliseconds between two progress updates of a job
+    #[serde(default = "default_progress_interval")]
+    pub(crate) progress_interval: u64,
+    /// Telegram bot that's told when jobs complete
+    pub(crate) telegram: Option<Telegram>,
+    /// Local command that's run when a job completes, for yodel running on a desktop
+    pub(crate) desktop_notification: Option<DesktopNotification>,
+    /// Bearer token for the admin endpoints, they're unavailable when this is not set
+    pub(crate) admin_token: Option<String>,
+    /// Bearer token that allows viewing the jobs, see `auth::check`
+    pub(crate) read_token: Option<String>,
+    /// Bearer token that allows everything, including adding and cancelling jobs
+    pub(crate) write_token: Option<String>,
+    /// Url that's sent a POST request with the job when a job completes
+    pub(crate) webhook_url: Option<String>,
+    /// Body of the webhook requests, instead of the job
+    pub(crate) webhook_template: Option<webhook::Template>,
+    /// Run youtube-dl with this niceness, from -20 to 19
+    pub(crate) nice: Option<i32>,
+    /// Run youtube-dl with this IO scheduling class, Linux only
+    pub(crate) io_priority: Option<IoPriority>,
+}
+
+/// Sends jobs without a location to `location` when their url matches
+/// Both `host` and `pattern` have to match when both are set
+#[derive(Debug, Deserialize)]
+pub(crate) struct RoutingRule {
+    /// Matches the host and its subdomains
+    host: Option<String>,
+    /// Regular expression that has to match somewhere in the url
+    pattern: Option<Pattern>,
+    location: String,
+}
+
+impl RoutingRule {
+    fn matches(&self, url: &str, host: &str) -> bool {
+        let host_matches = self
+            .host
+            .as_ref()
+            .is_none_or(|domain| matches_domain(host, domain));
+        let pattern_matches = self
+            .pattern
+            .as_ref()
+            .is_none_or(|pattern| pattern.0.is_match(url));
+        host_matches && pattern_matches
+    }
+}
+
+/// Options used for the jobs of a host, unless the job sets them itself
+#[derive(Debug, Deserialize)]
+pub(crate) struct Source {
+    /// Matches the host and its subdomains
+    host: String,
+    pub(crate) format_id: Option<String>,
+    pub(crate) subtitle_mode: Option<SubtitleMode>,
+    /// Put in front of the file names
+    pub(crate) prefix: Option<String>,
+}
+
+/// A regular expression that's compiled when the config is loaded
+#[derive(Debug)]
+pub(crate) struct Pattern(Regex);
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Pattern, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Regex::new(&pattern)
+            .map(Pattern)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// A value that's never logged, it might contain credentials
+#[derive(Deserialize)]
+#[serde(transparent)]
+pub(crate) struct Secret(String);
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+/// A location is either just a path, or a path with location specific settings
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum LocationConfig {
+    Path(PathBuf),
+    Detailed {
+        path: PathBuf,
+        /// Notified in addition to the global `webhook_url`
+        webhook_url: Option<String>,
+    },
+}
+
+impl LocationConfig {
+    fn path(&self) -> &PathBuf {
+        match self {
+            LocationConfig::Path(path) => path,
+            LocationConfig::Detailed { path, .. } => path,
+        }
+    }
+
+    fn path_mut(&mut self) -> &mut PathBuf {
+        match self {
+            LocationConfig::Path(path) => path,
+            LocationConfig::Detailed { path, .. } => path,
+        }
+    }
+
+    fn webhook_url(&self) -> Option<&str> {
+        match self {
+            LocationConfig::Path(_) => None,
+            LocationConfig::Detailed { webhook_url, .. } => webhook_url.as_deref(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum IoPriority {
+    /// Only use the disk when nothing else does
+    Idle,
+    /// Lowest priority of the normal scheduling class
+    BestEffort,
+}
+
+impl IoPriority {
+    fn ionice_args(self) -> &'static [&'static str] {
+        match self {
+            IoPriority::Idle => &["-c", "3"],
+            IoPriority::BestEffort => &["-c", "2", "-n", "7"],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum UpdateMethod {
+    /// The downloader's own `-U` flag, for the standalone binaries
+    #[default]
+    SelfUpdate,
+    /// `pip install --upgrade`, for downloaders installed with pip
+    Pip,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FreeSpace {
+    Bytes(u64),
+    /// Percentage of the filesystem's total size
+    Percentage(f64),
+}
+
+impl FreeSpace {
+    /// Fails when the filesystem of `path` has less free space than required
+    pub(crate) fn check(self, path: &Path) -> Result<(), String> {
+        let error = |e: std::io::Error| format!("Unable to check the free disk space: {}", e);
+        let available = fs2::available_space(path).map_err(error)?;
+        let required = match self {
+            FreeSpace::Bytes(bytes) => bytes,
+            FreeSpace::Percentage(percentage) => {
+                let total = fs2::total_space(path).map_err(error)?;
+                (total as f64 * percentage / 100.0) as u64
+            }
+        };
+
+        if available < required {
+            return Err(format!(
+                "Not enough free disk space: {} bytes available, {} bytes required",
+                available, required
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+fn default_downloader() -> PathBuf {
+    PathBuf::from("youtube-dl")
+}
+
+fn default_fetch_titles() -> bool {
+    true
+}
+
+fn default_title_cache_size() -> usize {
+    256
+}
+
+fn default_title_cache_ttl() -> u64 {
+    3600
+}
+
+fn default_auto_update_interval() -> u64 {
+    24
+}
+
+fn default_progress_interval() -> u64 {
+    500
+}
+
+/// The actix-web default
+fn default_max_connections() -> usize {
+    25_000
+}
+
+/// The actix-web default
+fn default_max_connection_rate() -> usize {
+    256
+}
+
+impl Config {
+    /// Parse a config file
+    /// Older config files only held the locations, eg: `name: /path`,
+    /// a file without a `locations` key is read as those locations
+    fn parse(contents: &str) -> Result<Config, serde_yaml::Error> {
+        let mut value: serde_yaml::Value = serde_yaml::from_str(contents)?;
+        let key = serde_yaml::Value::from("locations");
+        if let serde_yaml::Value::Mapping(mapping) = &value {
+            if !mapping.contains_key(&key) {
+                let mut wrapped = serde_yaml::Mapping::new();
+                wrapped.insert(key, value);
+                value = serde_yaml::Value::Mapping(wrapped);
+            }
+        }
+
+        serde_yaml::from_value(value)
+    }
+
+    /// Make the relative location paths absolute with the `base_dir`
+    fn resolve_locations(&mut self) {
+        let base_dir = match &self.base_dir {
+            Some(base_dir) => base_dir,
+            None => return,
+        };
+
+        for location in self.locations.values_mut() {
+            let path = location.path_mut();
+            if path.is_relative() {
+                *path = base_dir.join(&path);
+            }
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if let Some(base_dir) = &self.base_dir {
+            if !base_dir.is_absolute() {
+                return Err(format!("base_dir {:?} is not an absolute path", base_dir));
+            }
+        }
+
+        // youtube-dl runs in the location's directory, a relative path would depend on
+        // the directory yodel was started in
+        if let Some((name, location)) = self
+            .locations
+            .iter()
+            .find(|(_, location)| location.path().is_relative())
+        {
+            return Err(format!(
+                "location {} has a relative path {:?}, use an absolute path or set base_dir",
+                name,
+                location.path()
+            ));
+        }
+
+        if let Some(default_location) = &self.default_location {
+            if !self.locations.contains_key(default_location) {
+                return Err(format!(
+                    "default_location {} is not one of the locations",
+                    default_location
+                ));
+            }
+        }
+
+        if self.embed_chapters && !self.is_yt_dlp() {
+            return Err("embed_chapters requires yt-dlp".to_string());
+        }
+
+        for rule in &self.routing {
+            if rule.host.is_none() && rule.pattern.is_none() {
+                return Err(format!(
+                    "routing rule for {} needs a host or a pattern",
+                    rule.location
+                ));
+            }
+            if !self.locations.contains_key(&rule.location) {
+                return Err(format!(
+                    "routing rule location {} is not one of the locations",
+                    rule.location
+                ));
+            }
+        }
+
+        for source in &self.sources {
+            if let Some(format_id) = &source.format_id {
+                if !formats::is_valid_format_id(format_id) {
+                    return Err(format!(
+                        "source {} has an invalid format_id: {:?}",
+                        source.host, format_id
+                    ));
+                }
+            }
+            if let Some(prefix) = &source.prefix {
+                if prefix.contains(['/', '\\', '\0']) {
+                    return Err(format!(
+                        "source {} has an invalid prefix: {:?}",
+                        source.host, prefix
+                    ));
+                }
+            }
+            if source.subtitle_mode == Some(SubtitleMode::Both) && !self.is_yt_dlp() {
+                return Err(format!(
+                    "source {} keeps and embeds subtitles, which requires yt-dlp",
+                    source.host
+                ));
+            }
+        }
+
+        if let Some(nice) = self.nice {
+            if !(-20..=19).contains(&nice) {
+                return Err(format!("nice {} is not between -20 and 19", nice));
+            }
+        }
+
+        if let Some(format) = &self.merge_format {
+            if !jobs::MERGE_FORMATS.contains(&format.as_str()) {
+                return Err(format!(
+                    "merge_format {} is not one of: {}",
+                    format,
+                    jobs::MERGE_FORMATS.join(", ")
+                ));
+            }
+        }
+
+        if self.max_height == Some(0) {
+            return Err("max_height must be above 0".to_string());
+        }
+
+        if self.max_duration == Some(0) {
+            return Err("max_duration must be above 0".to_string());
+        }
+
+        if self.auto_update && self.auto_update_interval == 0 {
+            return Err("auto_update_interval must be above 0".to_string());
+        }
+
+        if self.max_connections == 0 || self.max_connection_rate == 0 {
+            return Err("max_connections and max_connection_rate must be above 0".to_string());
+        }
+
+        // youtube-dl runs in the download directory, a relative path would differ per location
+        if let Some(archive) = &self.download_archive {
+            if !archive.is_absolute() {
+                return Err(format!(
+                    "download_archive {:?} is not an absolute path",
+                    archive
+                ));
+            }
+        }
+
+        if let Some(template) = &self.webhook_template {
+            template.validate()?;
+        }
+
+        if let Some(notification) = &self.desktop_notification {
+            notification.validate()?;
+        }
+
+        if let Some(name) = self.environment.keys().find(|name| !is_env_name(name)) {
+            return Err(format!(
+                "{:?} is not a valid environment variable name",
+                name
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Names of the configured locations
+    pub(crate) fn location_names(&self) -> impl Iterator<Item = &String> {
+        self.locations.keys()
+    }
+
+    /// The location of the first routing rule that matches the url
+    pub(crate) fn route(&self, url: &str) -> Option<&str> {
+        let host = url::Url::parse(url).ok()?.host_str()?.to_string();
+        self.routing
+            .iter()
+            .find(|rule| rule.matches(url, &host))
+            .map(|rule| rule.location.as_str())
+    }
+
+    /// The options of the first source whose host matches the url
+    pub(crate) fn source(&self, url: &str) -> Option<&Source> {
+        let host = url::Url::parse(url).ok()?.host_str()?.to_string();
+        self.sources
+            .iter()
+            .find(|source| matches_domain(&host, &source.host))
+    }
+
+    /// Fails if the url's domain is blocked or not allowed
+    pub(crate) fn check_domain(&self, url: &str) -> Result<(), YodelError> {
+        if self.allowed_domains.is_empty() && self.blocked_domains.is_empty() {
+            return Ok(());
+        }
+
+        let host = url::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(String::from))
+            .ok_or_else(|| YodelError::BadRequest("Invalid URL".to_string()))?;
+
+        let matches = |domain: &String| matches_domain(&host, domain);
+        let allowed = self.allowed_domains.is_empty() || self.allowed_domains.iter().any(matches);
+        if !allowed || self.blocked_domains.iter().any(matches) {
+            return Err(YodelError::BadRequest(format!(
+                "Downloads from {} are not allowed",
+                host
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the downloader is yt-dlp, which supports options youtube-dl doesn't
+    pub(crate) fn is_yt_dlp(&self) -> bool {
+        self.downloader
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with("yt-dlp"))
+    }
+
+    /// Command that runs the downloader, with the configured priority and environment
+    pub(crate) fn downloader_command(&self) -> Command {
+        let mut command = match PRIORITY_WRAPPER.split_first() {
+            Some((program, args)) => {
+                let mut command = Command::new(program);
+                command.args(args).arg(&self.downloader);
+                command
+            }
+            None => Command::new(&self.downloader),
+        };
+        command.envs(
+            self.environment
+                .iter()
+                .map(|(name, Secret(value))| (name, value)),
+        );
+        command
+    }
+
+    /// Command that updates the downloader with the configured update method
+    pub(crate) fn update_command(&self) -> Command {
+        let mut command = match self.update_method {
+            UpdateMethod::SelfUpdate => {
+                let mut command = Command::new(&self.downloader);
+                command.arg("-U");
+                command
+            }
+            UpdateMethod::Pip => {
+                let package = if self.is_yt_dlp() {
+                    "yt-dlp"
+                } else {
+                    "youtube-dl"
+                };
+                let mut command = Command::new("pip");
+                command.args(["install", "--upgrade", package]);
+                command
+            }
+        };
+        // the update is downloaded through the same proxy as the videos
+        command.envs(
+            self.environment
+                .iter()
+                .map(|(name, Secret(value))| (name, value)),
+        );
+        command
+    }
+}
+
+/// Returns true for names made of letters, digits and underscores, not starting with a digit
+fn is_env_name(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| !c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Build the `nice`/`ionice` prefix for the downloader
+/// Settings that aren't supported on this platform are skipped
+fn priority_wrapper() -> Vec<String> {
+    let mut wrapper = Vec::new();
+
+    if let Some(nice) = CONFIG.nice {
+        if cfg!(unix) && program_exists("nice") {
+            wrapper.extend(vec!["nice".to_string(), "-n".to_string(), nice.to_string()]);
+        } else {
+            warn!("nice is not available, ignoring the nice setting");
+        }
+    }
+
+    if let Some(io_priority) = CONFIG.io_priority {
+        if cfg!(target_os = "linux") && program_exists("ionice") {
+            wrapper.push("ionice".to_string());
+            wrapper.extend(io_priority.ionice_args().iter().map(|arg| arg.to_string()));
+        } else {
+            warn!("ionice is not available, ignoring the io_priority setting");
+        }
+    }
+
+    wrapper
+}
+
+fn program_exists(program: &str) -> bool {
+    Command::new(program).arg("--version").output().is_ok()
+}
+
+/// Returns true if `host` is `domain` or one of its subdomains
+fn matches_domain(host: &str, domain: &str) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    let domain = domain.trim_matches('.').to_ascii_lowercase();
+
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum FilesystemType {
+    Local,
+    Network,
+    Unknown,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LocationInfo {
+    path: PathBuf,
+    filesystem_type: FilesystemType,
 }
 
 #[get("/locations")]
-async fn locations() -> impl Responder {
-    HttpResponse::Ok().json(LOCATIONS.clone())
+async fn locations() -> Result<HttpResponse, YodelError> {
+    // detecting the filesystem types reads the mount table, keep it off the async threads
+    let locations = web::block(|| -> Result<HashMap<String, LocationInfo>, YodelError> {
+        let locations = CONFIG
+            .locations
+            .iter()
+            .map(|(name, location)| {
+                let info = LocationInfo {
+                    path: location.path().clone(),
+                    filesystem_type: FILESYSTEM_TYPES
+                        .get(name)
+                        .copied()
+                        .unwrap_or(FilesystemType::Unknown),
+                };
+                (name.clone(), info)
+            })
+            .collect();
+
+        Ok(locations)
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(locations))
+}
+
+/// Best effort lookup of the type of filesystem `path` is mounted on
+#[cfg(target_os = "linux")]
+fn filesystem_type(path: &Path) -> FilesystemType {
+    let mounts = match std::fs::read_to_string("/proc/mounts") {
+        Ok(mounts) => mounts,
+        Err(e) => {
+            warn!("unable to read /proc/mounts: {}", e);
+            return FilesystemType::Unknown;
+        }
+    };
+
+    let path = match path.canonicalize() {
+        Ok(path) => path,
+        Err(_) => return FilesystemType::Unknown,
+    };
+
+    // the most specific mount point wins, later mounts shadow earlier ones on the same path
+    let fs_type = mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let _device = fields.next()?;
+            let mount_point = fields.next()?.replace("\\040", " ");
+            let fs_type = fields.next()?;
+            Some((PathBuf::from(mount_point), fs_type))
+        })
+        .filter(|(mount_point, _)| path.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.as_os_str().len())
+        .map(|(_, fs_type)| fs_type);
+
+    match fs_type {
+        Some(fs_type) if NETWORK_FILESYSTEMS.contains(&fs_type) => FilesystemType::Network,
+        Some(_) => FilesystemType::Local,
+        None => FilesystemType::Unknown,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn filesystem_type(_: &Path) -> FilesystemType {
+    FilesystemType::Unknown
 }
 
 #[derive(Hash, Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
@@ -26,7 +797,7 @@ pub struct Location {
 
 impl<'a> Location {
     pub(crate) fn lookup(name: &str) -> Option<Location> {
-        let path: PathBuf = LOCATIONS.get(name)?.clone();
+        let path: PathBuf = CONFIG.locations.get(name)?.path().clone();
 
         Some(Location {
             name: name.into(),
@@ -37,4 +808,157 @@ impl<'a> Location {
     pub(crate) fn path(&'a self) -> &'a PathBuf {
         &self.path
     }
+
+    pub(crate) fn name(&'a self) -> &'a str {
+        &self.name
+    }
+
+    /// The location's own webhook, see `LocationConfig`
+    pub(crate) fn webhook_url(&'a self) -> Option<&'a str> {
+        CONFIG.locations.get(&self.name)?.webhook_url()
+    }
+}
+
+#[cfg(test)]
+impl Location {
+    /// A location that doesn't have to be configured
+    pub(crate) fn new(name: &str, path: &Path) -> Location {
+        Location {
+            name: name.to_string(),
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(yaml: &str) -> Config {
+        Config::parse(yaml).unwrap()
+    }
+
+    fn location_path(config: &Config, name: &str) -> PathBuf {
+        config.locations[name].path().clone()
+    }
+
+    #[test]
+    fn a_config_with_only_locations_is_read_as_the_locations() {
+        let config = config(
+            "videos: /srv/videos
+music: /srv/music",
+        );
+
+        assert_eq!(
+            location_path(&config, "videos"),
+            PathBuf::from("/srv/videos")
+        );
+        assert_eq!(location_path(&config, "music"), PathBuf::from("/srv/music"));
+        assert_eq!(config.retries, None);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn relative_locations_are_resolved_against_the_base_dir() {
+        let mut config = config(
+            "locations:
+  videos: videos
+  music:
+    path: media/music
+  podcasts: /srv/podcasts
+base_dir: /data",
+        );
+        config.resolve_locations();
+
+        assert_eq!(
+            location_path(&config, "videos"),
+            PathBuf::from("/data/videos")
+        );
+        assert_eq!(
+            location_path(&config, "music"),
+            PathBuf::from("/data/media/music")
+        );
+        assert_eq!(
+            location_path(&config, "podcasts"),
+            PathBuf::from("/srv/podcasts")
+        );
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn relative_locations_are_rejected_without_a_base_dir() {
+        let mut config = config("locations: {videos: videos}");
+        config.resolve_locations();
+
+        assert_eq!(location_path(&config, "videos"), PathBuf::from("videos"));
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn a_relative_base_dir_is_rejected() {
+        let mut config = config(
+            "locations: {videos: /srv/videos}
+base_dir: data",
+        );
+        config.resolve_locations();
+
+        assert_eq!(
+            location_path(&config, "videos"),
+            PathBuf::from("/srv/videos")
+        );
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn matches_domain_includes_subdomains() {
+        assert!(matches_domain("youtube.com", "youtube.com"));
+        assert!(matches_domain("www.youtube.com", "youtube.com"));
+        assert!(matches_domain("m.www.youtube.com", ".youtube.com"));
+        assert!(!matches_domain("evilyoutube.com", "youtube.com"));
+        assert!(!matches_domain("youtube.com.evil.com", "youtube.com"));
+    }
+
+    #[test]
+    fn matches_domain_ignores_case_and_trailing_dots() {
+        assert!(matches_domain("WWW.YouTube.com", "youtube.com"));
+        assert!(matches_domain("www.youtube.com", "YOUTUBE.COM"));
+        assert!(matches_domain("www.youtube.com.", "youtube.com"));
+        assert!(matches_domain("youtube.com", "youtube.com."));
+    }
+
+    #[test]
+    fn check_domain_allows_and_blocks_domains() {
+        let config = config(
+            "locations: {}
+allowed_domains: [youtube.com]
+blocked_domains: [music.youtube.com]",
+        );
+
+        assert!(config
+            .check_domain("https://www.youtube.com/watch?v=1")
+            .is_ok());
+        assert!(config
+            .check_domain("https://YOUTUBE.COM./watch?v=1")
+            .is_ok());
+        assert!(matches!(
+            config.check_domain("https://evilyoutube.com/watch?v=1"),
+            Err(YodelError::BadRequest(_))
+        ));
+        assert!(matches!(
+            config.check_domain("https://music.youtube.com/watch?v=1"),
+            Err(YodelError::BadRequest(_))
+        ));
+        assert!(matches!(
+            config.check_domain("not a url"),
+            Err(YodelError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn check_domain_allows_everything_without_lists() {
+        let config = config("locations: {}");
+
+        assert!(config.check_domain("https://example.com/video").is_ok());
+        assert!(config.check_domain("not a url").is_ok());
+    }
 }
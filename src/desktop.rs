@@ -0,0 +1,69 @@
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::jobs::Job;
+
+/// Local command that's run when a job completes, eg: notify-send
+#[derive(Debug, Deserialize)]
+pub(crate) struct DesktopNotification {
+    #[serde(default)]
+    enabled: bool,
+    /// The program and its arguments, {{title}} and {{status}} are replaced in every argument
+    /// It's not run in a shell, so the title can't inject other commands
+    #[serde(default = "default_command")]
+    command: Vec<String>,
+}
+
+fn default_command() -> Vec<String> {
+    vec![
+        "notify-send".to_string(),
+        "{{title}}".to_string(),
+        "{{status}}".to_string(),
+    ]
+}
+
+impl DesktopNotification {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self
+            .command
+            .first()
+            .is_none_or(|program| program.is_empty())
+        {
+            return Err("desktop_notification needs a command".to_string());
+        }
+        Ok(())
+    }
+
+    /// Run the command on its own thread, failures are only logged
+    pub(crate) fn notify(&self, job: &Job) {
+        if !self.enabled {
+            return;
+        }
+
+        let title = job.to_string();
+        let status = job.status().name();
+        let mut args = self.command.iter().map(|arg| {
+            // the title goes last, so placeholders in the title are left alone
+            arg.replace("{{status}}", status)
+                .replace("{{title}}", &title)
+        });
+        let program = match args.next() {
+            Some(program) => program,
+            None => return,
+        };
+        let args: Vec<String> = args.collect();
+
+        std::thread::spawn(move || match Command::new(&program).args(&args).output() {
+            Ok(output) if output.status.success() => {
+                debug!("sent desktop notification");
+            }
+            Ok(output) => error!(
+                "desktop notification {} failed: {}",
+                program,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Err(e) => error!("unable to run desktop notification {}: {}", program, e),
+        });
+    }
+}
@@ -9,7 +9,27 @@ pub enum YodelError {
     BadRequest(String),
     #[display(fmt = "Job already exists: {}", _0)]
     Conflict(String),
+    #[display(fmt = "Not found: {}", _0)]
+    NotFound(String),
     TooManyJobs,
+    Unauthorized,
+    #[display(fmt = "Service unavailable: {}", _0)]
+    ServiceUnavailable(String),
+}
+
+impl YodelError {
+    /// Machine readable error code, used in websocket error messages
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            YodelError::InternalServerError => "internalServerError",
+            YodelError::BadRequest(_) => "badRequest",
+            YodelError::Conflict(_) => "conflict",
+            YodelError::NotFound(_) => "notFound",
+            YodelError::TooManyJobs => "tooManyJobs",
+            YodelError::Unauthorized => "unauthorized",
+            YodelError::ServiceUnavailable(_) => "serviceUnavailable",
+        }
+    }
 }
 
 impl ResponseError for YodelError {
@@ -20,8 +40,11 @@ impl ResponseError for YodelError {
             }
             YodelError::BadRequest(ref message) => HttpResponse::BadRequest().json(message),
             YodelError::Conflict(ref message) => HttpResponse::Conflict().json(message),
-            YodelError::TooManyJobs => {
-                HttpResponse::TooManyRequests().json("Too many running jobs")
+            YodelError::NotFound(ref message) => HttpResponse::NotFound().json(message),
+            YodelError::TooManyJobs => HttpResponse::TooManyRequests().json("Too many queued jobs"),
+            YodelError::Unauthorized => HttpResponse::Unauthorized().json("Unauthorized"),
+            YodelError::ServiceUnavailable(ref message) => {
+                HttpResponse::ServiceUnavailable().json(message)
             }
         }
     }
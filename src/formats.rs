@@ -0,0 +1,300 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use actix_web::{get, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::config::CONFIG;
+use crate::errors::YodelError;
+use crate::jobs::failure_reason;
+
+#[derive(Deserialize, Debug)]
+struct FormatsQuery {
+    url: String,
+}
+
+/// A format youtube-dl can download, pass its `formatId` to a job to download it
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all(serialize = "camelCase"))]
+struct Format {
+    format_id: String,
+    ext: Option<String>,
+    resolution: Option<String>,
+    format_note: Option<String>,
+    filesize: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EstimateQuery {
+    url: String,
+    /// Estimate the size of this format instead of the best one
+    format: Option<String>,
+}
+
+/// The size of a download for the format youtube-dl picks, summed across playlist items
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Estimate {
+    /// In bytes, `None` when none of the items have a known size
+    filesize: Option<u64>,
+    items: usize,
+    /// Items without a known size, they're not part of `filesize`
+    unknown_items: usize,
+    note: Option<String>,
+}
+
+/// The size youtube-dl reports for the format it would download
+#[derive(Deserialize, Debug)]
+struct SelectedFormat {
+    filesize: Option<u64>,
+    filesize_approx: Option<f64>,
+    /// The formats that are merged when video and audio are downloaded separately
+    #[serde(default)]
+    requested_formats: Vec<SelectedFormat>,
+}
+
+impl SelectedFormat {
+    fn size(&self) -> Option<u64> {
+        if let Some(size) = self
+            .filesize
+            .or(self.filesize_approx.map(|size| size as u64))
+        {
+            return Some(size);
+        }
+
+        if self.requested_formats.is_empty() {
+            return None;
+        }
+        self.requested_formats
+            .iter()
+            .map(SelectedFormat::size)
+            .sum()
+    }
+}
+
+/// The part of youtube-dl's json output we care about
+#[derive(Deserialize, Debug)]
+struct VideoInfo {
+    #[serde(default)]
+    formats: Vec<Format>,
+    #[serde(flatten)]
+    metadata: Metadata,
+}
+
+/// Describes a video, without downloading it
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct Metadata {
+    pub(crate) title: Option<String>,
+    /// In seconds
+    duration: Option<f64>,
+    uploader: Option<String>,
+    /// Url of the thumbnail image
+    thumbnail: Option<String>,
+}
+
+/// Read the metadata from an .info.json file written by youtube-dl
+pub(crate) fn read_metadata(path: &Path) -> std::io::Result<Metadata> {
+    let info: VideoInfo = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    Ok(info.metadata)
+}
+
+/// Format ids are passed to youtube-dl as-is, only allow the characters they're made of
+/// A video and an audio format are merged when they're joined with a '+', eg: 137+140
+pub(crate) fn is_valid_format_id(format_id: &str) -> bool {
+    format_id.len() <= 64
+        && format_id.split('+').all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+/// Format selectors that only pick audio, they have no height to limit
+const AUDIO_SELECTORS: &[&str] = &["bestaudio", "worstaudio", "ba", "wa"];
+
+/// The format selection passed to youtube-dl, the requested format is limited to `max_height`
+/// When the requested format is too tall, the best one that isn't is picked instead
+/// Formats without a known height, like audio formats, are never filtered out
+pub(crate) fn select_format(format_id: Option<&str>, max_height: Option<u32>) -> Option<String> {
+    let height = match max_height {
+        Some(height) => height,
+        None => return format_id.map(str::to_string),
+    };
+
+    if let Some(format_id) = format_id.filter(|format_id| AUDIO_SELECTORS.contains(format_id)) {
+        return Some(format_id.to_string());
+    }
+
+    let capped = format!(
+        "bestvideo[height<=?{0}]+bestaudio/best[height<=?{0}]",
+        height
+    );
+    Some(match format_id {
+        Some(format_id) => {
+            // every merged format is limited, eg: 137+140
+            let limited: Vec<String> = format_id
+                .split('+')
+                .map(|part| format!("{}[height<=?{}]", part, height))
+                .collect();
+            format!("{}/{}", limited.join("+"), capped)
+        }
+        None => capped,
+    })
+}
+
+/// List the formats available for a video
+#[get("/formats")]
+async fn formats(query: web::Query<FormatsQuery>) -> Result<HttpResponse, YodelError> {
+    let url = query.into_inner().url;
+    CONFIG.check_domain(&url)?;
+
+    let formats = web::block(move || -> Result<Vec<Format>, YodelError> {
+        let output = CONFIG
+            .downloader_command()
+            .arg("--dump-json")
+            .arg("--no-playlist")
+            .arg(&url)
+            .output()?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            error!("unable to list the formats of {}: {}", url, stderr);
+            return Err(YodelError::BadRequest(failure_reason(&stderr)));
+        }
+
+        let info: VideoInfo = serde_json::from_slice(&output.stdout).map_err(|e| {
+            error!("unable to parse the formats of {}: {}", url, e);
+            YodelError::InternalServerError
+        })?;
+
+        Ok(info.formats)
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(formats))
+}
+
+/// Estimate the size of a download without downloading it
+#[get("/estimate")]
+async fn estimate(query: web::Query<EstimateQuery>) -> Result<HttpResponse, YodelError> {
+    let EstimateQuery { url, format } = query.into_inner();
+    CONFIG.check_domain(&url)?;
+
+    let format = format.or_else(|| CONFIG.source(&url).and_then(|s| s.format_id.clone()));
+    if let Some(format) = &format {
+        if !is_valid_format_id(format) {
+            return Err(YodelError::BadRequest(format!(
+                "Invalid format id {}",
+                format
+            )));
+        }
+    }
+
+    let estimate = web::block(move || -> Result<Estimate, YodelError> {
+        let mut command = CONFIG.downloader_command();
+        command.arg("--dump-json");
+        if let Some(format) = select_format(format.as_deref(), CONFIG.max_height) {
+            command.arg("-f").arg(format);
+        }
+        let output = command.arg(&url).output()?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            error!("unable to estimate the size of {}: {}", url, stderr);
+            return Err(YodelError::BadRequest(failure_reason(&stderr)));
+        }
+
+        // playlists are dumped as one json object per item
+        let mut sizes = Vec::new();
+        for line in output.stdout.split(|&b| b == b'\n') {
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            let selected: SelectedFormat = serde_json::from_slice(line).map_err(|e| {
+                error!("unable to parse the formats of {}: {}", url, e);
+                YodelError::InternalServerError
+            })?;
+            sizes.push(selected.size());
+        }
+
+        let known: Vec<u64> = sizes.iter().filter_map(|size| *size).collect();
+        let unknown_items = sizes.len() - known.len();
+        let note = if known.is_empty() {
+            Some("The size of this format is unknown".to_string())
+        } else if unknown_items > 0 {
+            Some(format!(
+                "{} of {} items have an unknown size",
+                unknown_items,
+                sizes.len()
+            ))
+        } else {
+            None
+        };
+
+        Ok(Estimate {
+            filesize: if known.is_empty() {
+                None
+            } else {
+                Some(known.iter().sum())
+            },
+            items: sizes.len(),
+            unknown_items,
+            note,
+        })
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(estimate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_format_limits_the_height() {
+        assert_eq!(select_format(Some("137"), None), Some("137".to_string()));
+        assert_eq!(select_format(None, None), None);
+        assert_eq!(
+            select_format(None, Some(720)),
+            Some("bestvideo[height<=?720]+bestaudio/best[height<=?720]".to_string())
+        );
+        assert_eq!(
+            select_format(Some("137+140"), Some(720)),
+            Some(
+                "137[height<=?720]+140[height<=?720]/bestvideo[height<=?720]+bestaudio/best[height<=?720]"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn select_format_passes_audio_selectors_through() {
+        assert_eq!(
+            select_format(Some("bestaudio"), Some(720)),
+            Some("bestaudio".to_string())
+        );
+        assert_eq!(select_format(Some("ba"), Some(720)), Some("ba".to_string()));
+    }
+
+    #[test]
+    fn format_ids_can_be_merged() {
+        assert!(is_valid_format_id("137"));
+        assert!(is_valid_format_id("hls-1080p"));
+        assert!(is_valid_format_id("137+140"));
+        assert!(is_valid_format_id("bestvideo+bestaudio"));
+    }
+
+    #[test]
+    fn format_ids_cant_contain_selectors() {
+        assert!(!is_valid_format_id(""));
+        assert!(!is_valid_format_id("+"));
+        assert!(!is_valid_format_id("137+"));
+        assert!(!is_valid_format_id("137++140"));
+        assert!(!is_valid_format_id("best[height<=720]"));
+        assert!(!is_valid_format_id("137/140"));
+        assert!(!is_valid_format_id("137 --exec"));
+    }
+}
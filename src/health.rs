@@ -0,0 +1,45 @@
+use actix::prelude::*;
+use actix_web::{get, web, HttpResponse};
+use serde::Serialize;
+
+use crate::errors::YodelError;
+use crate::jobs::JobServer;
+use crate::version;
+
+/// Returns true when no more downloads can be started right now
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub(crate) struct AtCapacity;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Readiness {
+    ready: bool,
+    downloader_available: bool,
+    at_capacity: bool,
+}
+
+/// Liveness, succeeds as long as the job server responds
+#[get("/healthz")]
+async fn healthz(job_server: web::Data<Addr<JobServer>>) -> Result<HttpResponse, YodelError> {
+    job_server.send(AtCapacity).await?;
+    Ok(HttpResponse::Ok().json("ok"))
+}
+
+/// Readiness, fails with 503 when the downloader is missing or all download slots are taken
+#[get("/readyz")]
+async fn readyz(job_server: web::Data<Addr<JobServer>>) -> Result<HttpResponse, YodelError> {
+    let at_capacity = job_server.send(AtCapacity).await?;
+    let downloader_available = version::downloader_available();
+    let readiness = Readiness {
+        ready: downloader_available && !at_capacity,
+        downloader_available,
+        at_capacity,
+    };
+
+    if readiness.ready {
+        Ok(HttpResponse::Ok().json(readiness))
+    } else {
+        Ok(HttpResponse::ServiceUnavailable().json(readiness))
+    }
+}
@@ -1,105 +1,435 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
 use std::fmt;
+use std::fs;
 use std::hash::{Hash, Hasher};
-use std::process::Command;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use actix::prelude::*;
+use actix_files::NamedFile;
+use actix_web::http::{header, StatusCode};
 use actix_web::web::Json;
-use actix_web::{get, post, web, HttpResponse, Responder};
+use actix_web::{delete, get, post, put, web, Either, HttpRequest, HttpResponse, ResponseError};
 use chrono::{DateTime, Utc};
+use futures::channel::oneshot;
 use rand::{self, rngs::ThreadRng, Rng};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
 
-use crate::config::Location;
+use crate::admin;
+use crate::casing::{snake_case, Cased};
+use crate::config::{Location, CONFIG};
 use crate::errors::YodelError;
+use crate::formats::{self, Metadata};
+use crate::health::AtCapacity;
+use crate::repository;
+use crate::stats::{Counters, GetStats, JobCounts, OverallProgress, Stats};
+use crate::store::JobStore;
+use crate::title_cache::TitleCache;
+use crate::webhook;
+
+/// Containers youtube-dl can remux videos into
+const REMUX_FORMATS: &[&str] = &["mp4", "mkv", "webm", "mov", "avi", "flv"];
+
+/// Containers youtube-dl can merge separate video and audio streams into
+pub(crate) const MERGE_FORMATS: &[&str] = &["mkv", "mp4", "ogg", "webm", "flv"];
+
+/// Image formats yt-dlp can convert thumbnails into
+const THUMBNAIL_FORMATS: &[&str] = &["jpg", "png", "webp"];
+
+/// Browsers yt-dlp can read cookies from
+const COOKIE_BROWSERS: &[&str] = &[
+    "brave", "chrome", "chromium", "edge", "firefox", "opera", "safari", "vivaldi",
+];
+
+/// SponsorBlock categories yt-dlp can mark as chapters, "all" marks every category
+const SPONSORBLOCK_CATEGORIES: &[&str] = &[
+    "all",
+    "sponsor",
+    "intro",
+    "outro",
+    "selfpromo",
+    "preview",
+    "filler",
+    "interaction",
+    "music_offtopic",
+    "poi_highlight",
+    "chapter",
+];
+
+/// Extensions of the subtitle files youtube-dl writes
+const SUBTITLE_FORMATS: &[&str] = &[
+    "vtt", "srt", "ass", "ttml", "srv1", "srv2", "srv3", "json3", "lrc",
+];
+
+/// youtube-dl arguments whose value is a secret, or points to one
+const SECRET_ARGS: &[&str] = &[
+    "--cookies",
+    "--proxy",
+    "--geo-verification-proxy",
+    "--password",
+    "--video-password",
+    "--netrc-location",
+];
+
+/// Failures that won't go away by retrying, matched against the failure reason
+const PERMANENT_FAILURES: &[&str] = &[
+    "Unsupported URL",
+    "is not a valid URL",
+    "Video unavailable",
+    "Private video",
+    "Checksum mismatch",
+    "DRM protected",
+];
+
+/// Known youtube-dl failures, matched against its error output, with a clearer reason
+const KNOWN_FAILURES: &[(&str, &str)] = &[
+    (
+        "No space left on device",
+        "Disk full: no space left on device",
+    ),
+    (
+        "DRM protected",
+        "DRM protected: the video can't be downloaded",
+    ),
+    (
+        "known to use DRM protection",
+        "DRM protected: the site can't be downloaded from",
+    ),
+];
+
+/// youtube-dl's exit code when it stopped because of --max-downloads
+const MAX_DOWNLOADS_EXIT_CODE: i32 = 101;
+
+/// How long a long-polling client waits for changes
+const POLL_TIMEOUT: Duration = Duration::from_secs(30);
 
-#[derive(Clone)]
 pub(crate) struct JobServer {
-    jobs: HashSet<Job>,
-    sessions: HashMap<usize, Recipient<JobResponse>>,
+    store: JobStore,
+    sessions: HashMap<usize, Recipient<Broadcast>>,
+    /// Sequence number of the latest broadcast
+    seq: u64,
     rng: ThreadRng,
-    job_limit: usize,
+    /// When the most recent download was started for each host
+    last_dispatch: HashMap<String, Instant>,
+    /// Pending retry of the dispatcher for jobs waiting on a host cooldown
+    cooldown_timer: Option<SpawnHandle>,
+    /// Long-polling clients waiting for the jobs to change
+    waiters: Vec<oneshot::Sender<()>>,
+    counters: Counters,
+    /// Saves the counters in the background, see `Counters::writer`
+    counter_writer: mpsc::Sender<Counters>,
+    /// The youtube-dl processes of the running jobs
+    downloads: HashMap<Uuid, Arc<Download>>,
+    /// No new jobs are accepted or started while draining
+    draining: bool,
+    /// No jobs are started while the downloader is updated
+    updating: bool,
+    /// When the progress of each running job was last broadcast
+    last_progress: HashMap<Uuid, Instant>,
+    /// Pending broadcasts of progress that came in too soon after the previous one
+    progress_timers: HashMap<Uuid, SpawnHandle>,
+    /// Timers that stop the running jobs once they reach their max duration
+    duration_timers: HashMap<Uuid, SpawnHandle>,
+    titles: TitleCache,
 }
 
 impl JobServer {
     pub fn new() -> JobServer {
         JobServer {
-            jobs: HashSet::new(),
+            store: match repository::open() {
+                Some(repository) => JobStore::open(16, 64, repository),
+                None => JobStore::new(16, 64),
+            },
             sessions: HashMap::new(),
+            seq: 0,
             rng: rand::thread_rng(),
-            job_limit: 16,
+            last_dispatch: HashMap::new(),
+            cooldown_timer: None,
+            waiters: Vec::new(),
+            counters: Counters::load(),
+            counter_writer: Counters::writer(),
+            downloads: HashMap::new(),
+            draining: false,
+            updating: false,
+            last_progress: HashMap::new(),
+            progress_timers: HashMap::new(),
+            duration_timers: HashMap::new(),
+            titles: TitleCache::new(
+                CONFIG.title_cache_size,
+                Duration::from_secs(CONFIG.title_cache_ttl),
+            ),
+        }
+    }
+
+    /// Send the jobs that were added or changed since the last time, one message per job
+    /// Clients with a large history don't receive all jobs on every change this way
+    fn broadcast_changes(&mut self) {
+        for job in self.store.take_changed() {
+            self.broadcast(&JobResponse::JobUpdated(job));
         }
     }
 
     // Send a message to all connected clients
-    fn broadcast(&self, msg: &JobResponse) {
-        for session in self.sessions.values() {
-            let _ = session.do_send(msg.clone());
+    // Sessions that can't receive messages anymore are removed
+    fn broadcast(&mut self, msg: &JobResponse) {
+        self.seq += 1;
+        let broadcast = Broadcast {
+            seq: self.seq,
+            response: msg.clone(),
+        };
+        self.sessions
+            .retain(|id, session| match session.do_send(broadcast.clone()) {
+                Ok(()) => true,
+                Err(_) => {
+                    debug!("removing disconnected session {}", id);
+                    false
+                }
+            });
+    }
+
+    /// All jobs as a snapshot
+    /// It has the current seq, so the client applies every broadcast after it
+    fn snapshot(&self) -> Broadcast {
+        Broadcast {
+            seq: self.seq,
+            response: JobResponse::Snapshot {
+                pending: self.store.pending(),
+                completed: self.store.finished(),
+            },
         }
     }
 
-    /// Returns true if the server is running it's maximum allowed number of jobs
-    fn at_capacity(&self) -> bool {
-        let running_jobs = self.jobs.iter().filter(|&job| job.in_progress()).count();
-        running_jobs >= self.job_limit
+    /// Count the jobs for which `filter` returns true by status
+    fn job_counts<F>(&self, filter: F) -> JobCounts
+    where
+        F: Fn(&Job) -> bool,
+    {
+        JobCounts {
+            queued: self.store.count(|job| filter(job) && job.is_queued()),
+            in_progress: self.store.count(|job| filter(job) && job.in_progress()),
+            finished: self.store.count(|job| filter(job) && job.has_succeeded()),
+            failed: self.store.count(|job| filter(job) && job.has_failed()),
+        }
     }
 
-    /// Tries to add a job to the queue
-    /// Fails if the job was already added or the server is at capacity
-    fn add_job(&mut self, job: Job) -> Result<(), YodelError> {
-        if self.at_capacity() {
-            return Err(YodelError::TooManyJobs);
+    /// Broadcast the latest progress of a job right away
+    fn send_progress(&mut self, job: Job, ctx: &mut Context<Self>) {
+        if let Some(handle) = self.progress_timers.remove(&job.id) {
+            ctx.cancel_future(handle);
         }
+        self.last_progress.insert(job.id, Instant::now());
+        self.broadcast(&JobResponse::Progress(job));
+        self.notify_waiters();
+    }
 
-        if self.jobs.insert(job.clone()) {
-            Ok(())
-        } else {
-            Err(YodelError::Conflict(job.to_string()))
+    /// Stop throttling the progress of a job that's no longer running
+    fn forget_progress(&mut self, id: &Uuid, ctx: &mut Context<Self>) {
+        self.last_progress.remove(id);
+        if let Some(handle) = self.progress_timers.remove(id) {
+            ctx.cancel_future(handle);
         }
     }
 
-    pub(crate) fn start_job(&mut self, job: Job, addr: Addr<JobServer>) {
-        info!("starting job");
+    /// Stop the job once it ran for its max duration
+    fn limit_duration(&mut self, job: &Job, ctx: &mut Context<Self>) {
+        self.stop_duration_timer(&job.id, ctx);
+        if let Some(seconds) = job.options.max_duration {
+            let id = job.id;
+            let attempt = job.attempt;
+            let handle = ctx.run_later(Duration::from_secs(seconds), move |act, ctx| {
+                act.duration_timers.remove(&id);
+                act.expire(id, attempt, ctx);
+            });
+            self.duration_timers.insert(id, handle);
+        }
+    }
+
+    fn stop_duration_timer(&mut self, id: &Uuid, ctx: &mut Context<Self>) {
+        if let Some(handle) = self.duration_timers.remove(id) {
+            ctx.cancel_future(handle);
+        }
+    }
+
+    /// Kill the download of a job that exceeded its max duration and fail the job
+    /// Nothing happens when the job was restarted since, its new download gets its own time
+    fn expire(&mut self, id: Uuid, attempt: u32, ctx: &mut Context<Self>) {
+        let job = match self.store.get(&id) {
+            Some(job) if job.in_progress() && job.attempt == attempt => job.clone(),
+            _ => return,
+        };
+        if let Some(download) = self.downloads.get(&id) {
+            download.cancel();
+        }
+
+        warn!("{} exceeded its max duration", job);
+        // the job fails before youtube-dl is done, its own failure is ignored
+        ctx.notify(JobResponse::Failed {
+            job,
+            reason: "Max duration exceeded".to_string(),
+        });
+    }
+
+    /// Combine the progress of the running jobs, see `stats::OverallProgress`
+    fn overall_progress(&self) -> Option<OverallProgress> {
+        let progress: Vec<&Progress> = self
+            .store
+            .running()
+            .filter_map(|job| job.progress.as_ref())
+            .collect();
+        if progress.is_empty() {
+            return None;
+        }
+
+        let total: f64 = progress.iter().map(|progress| progress.percent).sum();
+        Some(OverallProgress {
+            jobs: progress.len(),
+            average_percent: total / progress.len() as f64,
+            eta: progress.iter().filter_map(|progress| progress.eta).max(),
+        })
+    }
+
+    fn drain_status(&self) -> admin::DrainStatus {
+        let in_progress = self.store.count(Job::in_progress);
+        admin::DrainStatus {
+            draining: self.draining,
+            in_progress,
+            queued: self.store.count(Job::is_queued),
+            idle: self.draining && in_progress == 0,
+        }
+    }
+
+    /// Update the downloader on another thread, the receiver resolves once it's done
+    fn update_downloader(
+        &mut self,
+        ctx: &mut Context<Self>,
+    ) -> Result<oneshot::Receiver<admin::UpdateResult>, YodelError> {
+        if self.updating {
+            return Err(YodelError::ServiceUnavailable(
+                "The downloader is already being updated".to_string(),
+            ));
+        }
+        if !self.downloads.is_empty() {
+            return Err(YodelError::ServiceUnavailable(format!(
+                "{} downloads are running",
+                self.downloads.len()
+            )));
+        }
+
+        self.updating = true;
+        let (sender, receiver) = oneshot::channel();
+        let addr = ctx.address();
         std::thread::spawn(move || {
-            let res = Command::new("youtube-dl")
-                .current_dir(job.location.path())
-                .arg("--no-overwrite")
-                .arg("--all-subs")
-                .arg("--embed-subs")
-                .arg("-o")
-                .arg("%(title)s.mp4")
-                .arg(&job.url)
-                .output();
+            let result = admin::update();
+            addr.do_send(admin::UpdateFinished);
+            // nobody waits for the result of automatic updates
+            let _ = sender.send(result);
+        });
 
-            debug!("finished");
-            match res {
-                Ok(output) => {
-                    if output.status.success() {
-                        info!("job succeeded!");
-                        addr.do_send(JobResponse::Finished(job));
-                    } else {
-                        let reason = String::from_utf8_lossy(&output.stderr).to_string();
-                        error!("youtube-dl failed: {:?}", reason);
-                        addr.do_send(JobResponse::Failed { job, reason });
-                    }
+        Ok(receiver)
+    }
+
+    /// Remove the sessions that are no longer connected
+    /// Returns the number of removed sessions
+    fn prune_sessions(&mut self) -> usize {
+        let before = self.sessions.len();
+        self.sessions.retain(|_, session| session.connected());
+        before - self.sessions.len()
+    }
+
+    /// Wake up the long-polling clients, the jobs have changed
+    fn notify_waiters(&mut self) {
+        for waiter in self.waiters.drain(..) {
+            let _ = waiter.send(());
+        }
+    }
+
+    /// Start queued jobs for as long as there is capacity
+    /// Jobs for a host that is still cooling down stay queued,
+    /// the dispatcher runs again once the first cooldown ends
+    fn dispatch(&mut self, ctx: &mut Context<Self>) {
+        if self.draining || self.updating {
+            return;
+        }
+
+        let cooldown = Duration::from_secs(CONFIG.host_cooldown);
+        self.last_dispatch
+            .retain(|_, started| started.elapsed() < cooldown);
+
+        loop {
+            let last_dispatch = &self.last_dispatch;
+            let downloads = &self.downloads;
+            let next = self.store.start_next(|job| {
+                // youtube-dl of a paused or cancelled attempt hasn't exited yet
+                if downloads.contains_key(&job.id) {
+                    return false;
                 }
-                Err(reason) => {
-                    // this is a server error
-                    error!("job startup failed: {}", reason);
-                    addr.do_send(JobResponse::Failed {
-                        job,
-                        reason: reason.to_string(),
-                    });
+                match job.host() {
+                    Some(host) => !last_dispatch.contains_key(&host),
+                    None => true,
                 }
+            });
+
+            let job = match next {
+                Some(job) => job,
+                None => break,
             };
+
+            if let Some(host) = job.host() {
+                if CONFIG.host_cooldown > 0 {
+                    self.last_dispatch.insert(host, Instant::now());
+                }
+            }
+            self.limit_duration(&job, ctx);
+            self.start_job(job, ctx.address());
+        }
+
+        if let Some(handle) = self.cooldown_timer.take() {
+            ctx.cancel_future(handle);
+        }
+
+        if self.store.at_capacity() {
+            return;
+        }
+
+        let next_cooldown_end = self
+            .store
+            .queued()
+            .filter_map(|job| self.last_dispatch.get(&job.host()?))
+            .map(|started| cooldown.checked_sub(started.elapsed()).unwrap_or_default())
+            .min();
+
+        if let Some(wait) = next_cooldown_end {
+            debug!("waiting {:?} for a host cooldown to end", wait);
+            self.cooldown_timer = Some(ctx.run_later(wait, |act, ctx| act.dispatch(ctx)));
+        }
+    }
+
+    pub(crate) fn start_job(&mut self, job: Job, addr: Addr<JobServer>) {
+        info!("starting job");
+        let download = Arc::new(Download::default());
+        self.downloads.insert(job.id, download.clone());
+        std::thread::spawn(move || {
+            let id = job.id;
+            let response = download_job(job, &download, &addr);
+            addr.do_send(response);
+            // youtube-dl exited, the job can be started again without two processes
+            // writing the same files
+            addr.do_send(DownloadEnded { id, download });
         });
     }
 
     fn search_title(&mut self, job: Job, addr: Addr<JobServer>) {
         std::thread::spawn(move || {
-            let res = Command::new("youtube-dl")
+            let res = CONFIG
+                .downloader_command()
                 .arg("--get-title")
+                .args(job.options.lookup_args())
                 .arg(&job.url)
                 .output();
 
@@ -122,314 +452,3751 @@ impl JobServer {
             }
         });
     }
-
-    fn pending_jobs(&self) -> Vec<Job> {
-        self.jobs
-            .iter()
-            .filter(|job: &&Job| job.in_progress())
-            .cloned()
-            .collect()
-    }
-
-    fn finished_jobs(&self) -> Vec<Job> {
-        self.jobs
-            .iter()
-            .filter(|job: &&Job| job.is_completed())
-            .cloned()
-            .collect()
-    }
-
-    /// Save an existing job with new values
-    /// panics if the job didn't exist yet
-    fn save(&mut self, job: Job) {
-        self.jobs
-            .replace(job)
-            .expect("The job should already exist");
-    }
 }
 
-#[derive(Eq, PartialEq, Debug, Clone, Serialize)]
+#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub enum JobStatus {
     Finished,
     Failed(String),
     InProgress,
+    /// Waiting for a free download slot
+    Queued,
+    /// Downloaded to the staging directory, waiting to be approved or rejected
+    AwaitingReview,
+    /// Stopped halfway, the partial download is continued when the job is resumed
+    Paused,
 }
 
-#[derive(Debug, Clone, Serialize, Message)]
+impl JobStatus {
+    /// Name of the status without the failure reason
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            JobStatus::Finished => "finished",
+            JobStatus::Failed(_) => "failed",
+            JobStatus::InProgress => "inProgress",
+            JobStatus::Queued => "queued",
+            JobStatus::AwaitingReview => "awaitingReview",
+            JobStatus::Paused => "paused",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
 #[rtype(result = "()")]
 #[serde(rename_all = "camelCase")]
 pub struct Job {
+    id: Uuid,
     url: String,
     title: Option<String>,
     location: Location,
+    /// The download is copied to these locations once it's in its own location
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    extra_locations: Vec<Location>,
+    /// Lowercase labels to organize the jobs, they're not part of the job's identity
+    #[serde(default)]
+    tags: Vec<String>,
     started_on: DateTime<Utc>,
     status: JobStatus,
+    options: JobOptions,
+    /// The youtube-dl output template, see `JobOptions::output_template`
+    output_template: String,
+    output: JobOutput,
+    /// Downloaded to the staging directory instead of the location
+    staged: bool,
+    /// The latest progress of a running download
+    #[serde(skip_serializing_if = "Option::is_none")]
+    progress: Option<Progress>,
+    /// The file youtube-dl is downloading right now, see `job_file`
+    #[serde(skip)]
+    destination: Option<PathBuf>,
+    /// Counts the times the job was started
+    /// A cancelled download can still report back after the job was retried,
+    /// its messages are told apart from the new download's by their attempt
+    #[serde(default)]
+    attempt: u32,
 }
 
-impl Job {
-    fn in_progress(&self) -> bool {
-        self.status == JobStatus::InProgress
-    }
-
-    #[allow(dead_code)]
-    fn has_failed(&self) -> bool {
-        match self.status {
-            JobStatus::Failed(_) => true,
-            _ => false,
-        }
-    }
-
-    #[allow(dead_code)]
-    fn has_succeeded(&self) -> bool {
-        self.status == JobStatus::Finished
-    }
-
-    /// return all completed jobs, failed or not
-    fn is_completed(&self) -> bool {
-        !self.in_progress()
-    }
-
-    fn set_finished(&mut self) {
-        self.status = JobStatus::Finished;
-    }
-
-    fn set_failed(&mut self, reason: String) {
-        self.status = JobStatus::Failed(reason);
-    }
-
-    fn set_title(&mut self, title: String) {
-        self.title = Some(title);
-    }
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Progress {
+    percent: f64,
+    /// eg: 1.23MiB/s
+    speed: Option<String>,
+    /// Estimated number of seconds until the download finishes
+    eta: Option<u64>,
 }
 
-impl TryFrom<JobRequest> for Job {
-    type Error = YodelError;
+impl Progress {
+    /// Parse a youtube-dl progress line, eg:
+    /// `[download]  42.0% of 10.00MiB at  1.23MiB/s ETA 00:42`
+    /// The speed and ETA can be "Unknown speed" and "Unknown ETA"
+    fn parse(line: &str) -> Option<Progress> {
+        let line = line.strip_prefix("[download]")?.trim_start();
+        let percent_end = line.find('%')?;
+        let percent = line[..percent_end].trim().parse().ok()?;
+        let rest = &line[percent_end + 1..];
 
-    fn try_from(request: JobRequest) -> Result<Job, Self::Error> {
-        let location = match Location::lookup(&request.location) {
-            Some(location) => location,
-            None => {
-                return Err(YodelError::BadRequest("Invalid Location".to_string()));
-            }
-        };
+        let speed = rest
+            .split(" at ")
+            .nth(1)
+            .and_then(|speed| speed.split(" ETA ").next())
+            .map(str::trim)
+            .filter(|speed| !speed.starts_with("Unknown"))
+            .map(String::from);
 
-        Ok(Job {
-            url: request.url,
-            title: None,
-            location,
-            started_on: Utc::now(),
-            status: JobStatus::InProgress,
-        })
-    }
-}
+        let eta = rest.split(" ETA ").nth(1).and_then(parse_duration);
 
-impl Hash for Job {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.url.hash(state);
-        self.location.hash(state);
+        Some(Progress {
+            percent,
+            speed,
+            eta,
+        })
     }
 }
 
-impl PartialEq for Job {
-    fn eq(&self, other: &Self) -> bool {
-        self.url.eq(&other.url) && self.location.eq(&other.location)
-    }
+/// Parse a `[[HH:]MM:]SS` duration into seconds
+fn parse_duration(duration: &str) -> Option<u64> {
+    duration.trim().split(':').try_fold(0, |seconds, part| {
+        Some(seconds * 60 + part.parse::<u64>().ok()?)
+    })
 }
 
-impl Eq for Job {}
+/// Fails with the first of the snake_case option names that isn't in `allowed`
+/// Every option is allowed when there's no list
+fn check_allowed_names<I>(names: I, allowed: Option<&[String]>) -> Result<(), YodelError>
+where
+    I: IntoIterator<Item = String>,
+{
+    let allowed = match allowed {
+        Some(allowed) => allowed,
+        None => return Ok(()),
+    };
 
-impl fmt::Display for Job {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if let Some(title) = &self.title {
-            write!(f, "{}", title)
-        } else {
-            write!(f, "{}", self.url)
-        }
+    match names.into_iter().find(|name| !allowed.contains(name)) {
+        Some(name) => Err(YodelError::BadRequest(format!(
+            "Option {} is not allowed",
+            name
+        ))),
+        None => Ok(()),
     }
 }
 
-#[derive(Deserialize, Debug, Message)]
-#[rtype(result = "Result<Job, YodelError>")]
-struct JobRequest {
-    url: String,
-    location: String,
+/// youtube-dl options that can be set per job
+/// These are part of a job's identity, the same url can be downloaded to the same location
+/// with different options, eg: once as video and once remuxed into another container
+/// Saved jobs store the options in camelCase, the aliases read them back
+#[derive(Deserialize, Serialize, Debug, Clone, Default, Hash, Eq, PartialEq)]
+#[serde(default, rename_all(serialize = "camelCase"))]
+pub struct JobOptions {
+    /// Number of youtube-dl retries for the download and its fragments
+    retries: Option<u32>,
+    /// Save the video description to a .description file
+    #[serde(alias = "writeDescription")]
+    write_description: bool,
+    /// Save the video comments in an .info.json file, yt-dlp only
+    #[serde(alias = "writeComments")]
+    write_comments: bool,
+    /// Remux the video into this container, eg: mkv, yt-dlp only
+    remux: Option<String>,
+    /// Container of videos merged from separate video and audio streams, eg: mkv
+    /// Defaults to the configured value
+    #[serde(alias = "mergeFormat")]
+    merge_format: Option<String>,
+    /// Fake the X-Forwarded-For header to bypass geographic restrictions
+    #[serde(alias = "geoBypass")]
+    geo_bypass: bool,
+    /// Two letter ISO 3166-2 country code to use for the geo bypass
+    #[serde(alias = "geoBypassCountry")]
+    geo_bypass_country: Option<String>,
+    /// Embed chapter markers, yt-dlp only
+    /// Defaults to the configured value
+    #[serde(alias = "embedChapters")]
+    embed_chapters: Option<bool>,
+    /// Mark these SponsorBlock categories as chapters, the video is kept whole, yt-dlp only
+    #[serde(alias = "sponsorblockMark")]
+    sponsorblock_mark: Option<Vec<String>>,
+    #[serde(alias = "subtitleMode")]
+    subtitle_mode: SubtitleMode,
+    /// Literal name of the downloaded file, instead of the video title
+    /// The extension is added when it's missing, and replaced when remuxing,
+    /// picking a format or extracting the audio decides the container
+    filename: Option<String>,
+    /// Download this exact format, as listed by `/formats`
+    #[serde(alias = "formatId")]
+    format_id: Option<String>,
+    /// Only use ASCII characters without spaces in the file name
+    /// Defaults to the configured value
+    #[serde(alias = "restrictFilenames")]
+    restrict_filenames: Option<bool>,
+    /// Prefer free formats, like webm, over others of the same quality
+    /// Defaults to the configured value
+    #[serde(alias = "preferFreeFormats")]
+    prefer_free_formats: Option<bool>,
+    /// Seconds after which the download is stopped and fails
+    /// Defaults to the configured value, which it can't exceed
+    /// Live recordings, see `allow_live`, only stop at their own limit
+    #[serde(alias = "maxDuration")]
+    max_duration: Option<u64>,
+    /// Only connect over IPv4 ("4") or IPv6 ("6")
+    #[serde(alias = "forceIp")]
+    force_ip: Option<String>,
+    /// Name of a youtube-dl options file in the configured `config_files_dir`
+    #[serde(alias = "configFile")]
+    config_file: Option<String>,
+    /// Use the cookies of this browser's profile on the server, eg: firefox, yt-dlp only
+    #[serde(alias = "cookiesFromBrowser")]
+    cookies_from_browser: Option<String>,
+    /// Embed the info json in the video file, yt-dlp only
+    #[serde(alias = "embedInfoJson")]
+    embed_info_json: bool,
+    /// Only keep the audio
+    #[serde(alias = "audioOnly")]
+    audio_only: bool,
+    /// Keep the video the audio was extracted from, only allowed with `audio_only`
+    #[serde(alias = "keepVideo")]
+    keep_video: bool,
+    /// Quality of the extracted audio, from 0 (best) to 9 (worst) or a bitrate like 192K
+    /// Only allowed with `audio_only`
+    #[serde(alias = "audioQuality")]
+    audio_quality: Option<String>,
+    /// Save the thumbnail as an image next to the video
+    #[serde(alias = "writeThumbnail")]
+    write_thumbnail: bool,
+    /// Convert the saved thumbnail to this format, eg: jpg, yt-dlp only
+    #[serde(alias = "thumbnailFormat")]
+    thumbnail_format: Option<String>,
+    /// Put in front of the file name, eg: "2021-01-01 "
+    prefix: Option<String>,
+    /// Only download these playlist items, eg: "1-3,7,10-13"
+    #[serde(alias = "playlistItems")]
+    playlist_items: Option<String>,
+    /// The job fails when the SHA-256 of the downloaded file is different, in hex
+    #[serde(alias = "expectedSha256")]
+    expected_sha256: Option<String>,
+    /// Record live streams until they end, they're skipped otherwise
+    #[serde(alias = "allowLive")]
+    allow_live: bool,
+    /// Only look up the metadata of the video, see `JobOutput::metadata`
+    #[serde(alias = "metadataOnly")]
+    metadata_only: bool,
+    /// Only download the subtitle files, they're kept as sidecars
+    #[serde(alias = "subtitlesOnly")]
+    subtitles_only: bool,
+    /// Comma separated subtitle languages, eg: "en,nl", all languages when not set
+    /// Only allowed with `subtitles_only`
+    #[serde(alias = "subtitleLangs")]
+    subtitle_langs: Option<String>,
 }
 
-impl Handler<JobRequest> for JobServer {
-    type Result = Result<Job, YodelError>;
-
-    fn handle(&mut self, request: JobRequest, ctx: &mut Context<Self>) -> Self::Result {
-        debug!("Request received: {:?}", request);
-
-        let job = Job::try_from(request)?;
+impl JobOptions {
+    /// Arguments that change how youtube-dl reaches the video,
+    /// these apply to both the download and the title lookup
+    fn lookup_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
 
-        self.add_job(job.clone())?;
-
-        self.start_job(job.clone(), ctx.address());
-        self.search_title(job.clone(), ctx.address());
-        self.broadcast(JobResponse::PendingJobs(self.pending_jobs()).as_ref());
-        Ok(job)
-    }
-}
+        if self.geo_bypass {
+            args.push("--geo-bypass".to_string());
+        }
 
-#[derive(Message)]
-#[rtype(result = "()")]
-struct VideoTitle {
-    job: Job,
-    title: String,
-}
+        if let Some(country) = &self.geo_bypass_country {
+            args.push("--geo-bypass-country".to_string());
+            args.push(country.to_uppercase());
+        }
 
-impl Handler<VideoTitle> for JobServer {
-    type Result = ();
+        if let Some(version) = &self.force_ip {
+            args.push(format!("-{}", version));
+        }
 
-    fn handle(&mut self, video_title: VideoTitle, _: &mut Context<Self>) -> Self::Result {
-        let mut job = self
-            .jobs
-            .take(&video_title.job)
-            .expect("The job can not be none");
-        let finished = job.is_completed();
-        job.set_title(video_title.title);
-        self.jobs.insert(job);
-
-        if finished {
-            self.broadcast(JobResponse::CompletedJobs(self.finished_jobs()).as_ref());
-        } else {
-            self.broadcast(JobResponse::PendingJobs(self.pending_jobs()).as_ref());
+        if let Some(path) = self.config_path() {
+            args.push("--config-location".to_string());
+            args.push(path.to_string_lossy().into_owned());
         }
-    }
-}
 
-#[derive(Message)]
-#[rtype(usize)]
-pub(crate) struct Connect {
-    pub(crate) addr: Recipient<JobResponse>,
-}
+        if let Some(browser) = &self.cookies_from_browser {
+            args.push("--cookies-from-browser".to_string());
+            args.push(browser.clone());
+        }
 
-#[derive(Message)]
-#[rtype(result = "()")]
-pub struct Disconnect {
-    pub id: usize,
-}
+        args
+    }
 
-#[derive(Message)]
-#[rtype(result = "Result<Vec<Job>, std::io::Error>")]
-pub enum JobQuery {
-    Pending,
-    Completed,
-}
+    /// Path of the requested options file
+    fn config_path(&self) -> Option<PathBuf> {
+        let directory = CONFIG.config_files_dir.as_ref()?;
+        Some(directory.join(self.config_file.as_ref()?))
+    }
 
-/// User facing messages
-#[derive(Debug, Message, Serialize, Clone)]
-#[rtype(result = "()")]
-pub(crate) enum JobResponse {
-    // Start(Job),
-    Finished(Job),
-    /// job finished, but unsuccessfully
-    Failed {
-        job: Job,
-        reason: String,
-    },
-    PendingJobs(Vec<Job>),
-    CompletedJobs(Vec<Job>),
-}
+    /// Names of the options that were set to something other than their default
+    fn requested(&self) -> Vec<String> {
+        let defaults = serde_json::to_value(JobOptions::default()).unwrap_or_default();
+        let options = match serde_json::to_value(self) {
+            Ok(serde_json::Value::Object(options)) => options,
+            _ => return Vec::new(),
+        };
 
-impl AsRef<JobResponse> for JobResponse {
-    fn as_ref(&self) -> &JobResponse {
-        &self
+        options
+            .into_iter()
+            .filter(|(name, value)| defaults.get(name) != Some(value))
+            .map(|(name, _)| snake_case(&name))
+            .collect()
     }
-}
-
-impl Actor for JobServer {
-    type Context = Context<Self>;
-}
 
-impl Handler<Connect> for JobServer {
-    type Result = usize;
+    /// Fails with the first requested option that isn't in the configured `allowed_options`
+    fn check_allowed(&self) -> Result<(), YodelError> {
+        check_allowed_names(self.requested(), CONFIG.allowed_options.as_deref())
+    }
 
-    fn handle(&mut self, msg: Connect, _ctx: &mut Context<Self>) -> Self::Result {
-        let session_id = self.rng.gen::<usize>();
-        self.sessions.insert(session_id, msg.addr);
+    /// The options with the given options replaced, `changes` can be in snake_case or camelCase
+    fn merge(
+        &self,
+        changes: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<JobOptions, YodelError> {
+        let mut options = match serde_json::to_value(self) {
+            Ok(serde_json::Value::Object(options)) => options,
+            _ => return Err(YodelError::InternalServerError),
+        };
 
-        info!("new connection!");
+        // compare the names in snake_case, so a change replaces the option in either casing
+        options = options
+            .into_iter()
+            .map(|(name, value)| (snake_case(&name), value))
+            .collect();
+        for (name, value) in changes {
+            let name = snake_case(&name);
+            if !options.contains_key(&name) {
+                return Err(YodelError::BadRequest(format!("Unknown option {}", name)));
+            }
+            options.insert(name, value);
+        }
 
-        session_id
+        serde_json::from_value(serde_json::Value::Object(options))
+            .map_err(|e| YodelError::BadRequest(format!("Invalid options: {}", e)))
     }
-}
 
-impl Handler<Disconnect> for JobServer {
-    type Result = ();
+    /// Fill in the options that weren't requested with the configured ones for the url's host
+    fn with_source(mut self, url: &str) -> JobOptions {
+        let source = match CONFIG.source(url) {
+            Some(source) => source,
+            None => return self,
+        };
 
-    fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
-        info!("connection lost");
-        self.sessions.remove(&msg.id);
+        if self.format_id.is_none() {
+            self.format_id = source.format_id.clone();
+        }
+        if self.prefix.is_none() {
+            self.prefix = source.prefix.clone();
+        }
+        // the default can't be told apart from a requested embed
+        if self.subtitle_mode == SubtitleMode::default() {
+            if let Some(mode) = source.subtitle_mode {
+                self.subtitle_mode = mode;
+            }
+        }
+        self
     }
-}
-
-impl Handler<JobQuery> for JobServer {
-    type Result = Result<Vec<Job>, std::io::Error>;
 
-    fn handle(&mut self, query: JobQuery, _: &mut Context<Self>) -> Self::Result {
-        match query {
-            JobQuery::Pending => Ok(self.pending_jobs()),
-            JobQuery::Completed => Ok(self.finished_jobs()),
+    /// Fill in the options that weren't requested with the configured defaults
+    fn with_defaults(mut self) -> JobOptions {
+        self.retries = self.retries.or(CONFIG.retries);
+        self.embed_chapters = self.embed_chapters.or(Some(CONFIG.embed_chapters));
+        self.restrict_filenames = self.restrict_filenames.or(Some(CONFIG.restrict_filenames));
+        // a live stream is recorded until it ends, only its own limit applies
+        if !self.allow_live {
+            self.max_duration = self.max_duration.or(CONFIG.max_duration);
+        }
+        // audio files aren't merged
+        if !self.audio_only {
+            self.merge_format = self.merge_format.or_else(|| CONFIG.merge_format.clone());
         }
+        self.prefer_free_formats = self
+            .prefer_free_formats
+            .or(Some(CONFIG.prefer_free_formats));
+        self.filename = self.filename.map(|filename| filename.trim().to_string());
+        self.expected_sha256 = self.expected_sha256.map(|hash| hash.to_ascii_lowercase());
+        self
     }
-}
 
-impl Handler<JobResponse> for JobServer {
-    type Result = ();
+    /// The youtube-dl output template for the downloaded file
+    /// Only the title and ext fields are used, the requested parts are escaped into literals,
+    /// so the template can't reference fields youtube-dl doesn't know
+    fn output_template(&self) -> String {
+        // the extension changes when remuxing, picking a format or extracting the audio,
+        // let youtube-dl fill it in
+        let extension = if self.remux.is_some() || self.format_id.is_some() || self.audio_only {
+            "%(ext)s"
+        } else {
+            self.merge_format.as_deref().unwrap_or("mp4")
+        };
 
-    fn handle(&mut self, msg: JobResponse, _ctx: &mut Context<Self>) {
-        info!("Request received: {:?}", msg);
-        match msg.clone() {
-            JobResponse::Finished(mut job) => {
-                job.set_finished();
-                self.save(job);
-                self.broadcast(&msg);
-                self.broadcast(JobResponse::PendingJobs(self.pending_jobs()).as_ref());
-                self.broadcast(JobResponse::CompletedJobs(self.finished_jobs()).as_ref());
-            }
-            JobResponse::Failed { mut job, reason } => {
-                job.set_failed(reason);
-                self.save(job);
-                self.broadcast(&msg);
-                self.broadcast(JobResponse::PendingJobs(self.pending_jobs()).as_ref());
-                self.broadcast(JobResponse::CompletedJobs(self.finished_jobs()).as_ref());
+        // '%' starts a template field, "%%" is a literal '%'
+        let prefix = self
+            .prefix
+            .as_deref()
+            .unwrap_or_default()
+            .replace('%', "%%");
+
+        match &self.filename {
+            Some(filename) => {
+                let path = Path::new(filename);
+                let name = match (path.file_stem(), path.extension()) {
+                    // youtube-dl picks the container, a requested extension would be wrong
+                    (Some(stem), Some(_)) if extension == "%(ext)s" => stem.to_string_lossy(),
+                    (_, Some(_)) => return format!("{}{}", prefix, filename.replace('%', "%%")),
+                    _ => filename.into(),
+                };
+                format!("{}{}.{}", prefix, name.replace('%', "%%"), extension)
             }
-            _ => (),
+            None => format!("{}%(title)s.{}", prefix, extension),
         }
     }
-}
 
-#[post("/jobs")]
-async fn create_job(
-    request: Json<JobRequest>,
+    fn validate(&self) -> Result<(), YodelError> {
+        if let Some(hash) = &self.expected_sha256 {
+            if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(YodelError::BadRequest(format!(
+                    "Invalid SHA-256: {}, expected 64 hexadecimal characters",
+                    hash
+                )));
+            }
+        }
+
+        if let Some(items) = &self.playlist_items {
+            if !is_valid_playlist_items(items) {
+                return Err(YodelError::BadRequest(format!(
+                    "Invalid playlist items: {}, expected eg: 1-3,7,10-13",
+                    items
+                )));
+            }
+        }
+
+        if let Some(prefix) = &self.prefix {
+            if prefix.contains(['/', '\\', '\0']) {
+                return Err(YodelError::BadRequest(format!(
+                    "Invalid prefix: {:?}",
+                    prefix
+                )));
+            }
+        }
+
+        if let Some(filename) = &self.filename {
+            let filename = filename.trim();
+            let is_special = filename.is_empty() || filename == "." || filename == "..";
+            if is_special || filename.contains(['/', '\\', '\0']) {
+                return Err(YodelError::BadRequest(format!(
+                    "Invalid filename: {:?}",
+                    filename
+                )));
+            }
+        }
+
+        if let Some(format_id) = &self.format_id {
+            if !formats::is_valid_format_id(format_id) {
+                return Err(YodelError::BadRequest(format!(
+                    "Invalid format id: {:?}",
+                    format_id
+                )));
+            }
+        }
+
+        if let Some(seconds) = self.max_duration {
+            if seconds == 0 {
+                return Err(YodelError::BadRequest(
+                    "The max duration must be above 0".to_string(),
+                ));
+            }
+            let limit = CONFIG.max_duration.filter(|_| !self.allow_live);
+            if let Some(max) = limit.filter(|max| seconds > *max) {
+                return Err(YodelError::BadRequest(format!(
+                    "The max duration can't exceed {} seconds",
+                    max
+                )));
+            }
+        }
+
+        if let Some(version) = &self.force_ip {
+            if version != "4" && version != "6" {
+                return Err(YodelError::BadRequest(format!(
+                    "Invalid IP version: {}, expected 4 or 6",
+                    version
+                )));
+            }
+        }
+
+        if let Some(country) = &self.geo_bypass_country {
+            if country.len() != 2 || !country.chars().all(|c| c.is_ascii_alphabetic()) {
+                return Err(YodelError::BadRequest(format!(
+                    "Invalid geo bypass country: {}, expected a two letter country code",
+                    country
+                )));
+            }
+        }
+
+        if self.write_comments && !CONFIG.is_yt_dlp() {
+            return Err(YodelError::BadRequest(
+                "Writing comments requires yt-dlp".to_string(),
+            ));
+        }
+
+        if let Some(name) = &self.config_file {
+            let directory = CONFIG.config_files_dir.as_ref().ok_or_else(|| {
+                YodelError::BadRequest("No config files are configured".to_string())
+            })?;
+            // the error doesn't tell which files exist, or where they are
+            if find_config_file(name, directory).is_none() {
+                warn!("refusing unknown config file {:?}", name);
+                return Err(YodelError::BadRequest("Unknown config file".to_string()));
+            }
+        }
+
+        if let Some(browser) = &self.cookies_from_browser {
+            if !CONFIG.is_yt_dlp() {
+                return Err(YodelError::BadRequest(
+                    "Cookies from a browser require yt-dlp".to_string(),
+                ));
+            }
+
+            if !COOKIE_BROWSERS.contains(&browser.as_str()) {
+                return Err(YodelError::BadRequest(format!(
+                    "Unsupported browser: {}, expected one of: {}",
+                    browser,
+                    COOKIE_BROWSERS.join(", ")
+                )));
+            }
+        }
+
+        if self.subtitles_only && (self.metadata_only || self.audio_only) {
+            return Err(YodelError::BadRequest(
+                "subtitles_only can't be combined with metadata_only or audio_only".to_string(),
+            ));
+        }
+
+        if let Some(langs) = &self.subtitle_langs {
+            if !self.subtitles_only {
+                return Err(YodelError::BadRequest(
+                    "Subtitle languages require subtitles_only".to_string(),
+                ));
+            }
+            let is_lang = |lang: &str| {
+                !lang.is_empty()
+                    && lang
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            };
+            if !langs.split(',').all(|lang| is_lang(lang.trim())) {
+                return Err(YodelError::BadRequest(format!(
+                    "Invalid subtitle languages: {}, expected eg: en,nl",
+                    langs
+                )));
+            }
+        }
+
+        if (self.metadata_only || self.subtitles_only) && self.expected_sha256.is_some() {
+            return Err(YodelError::BadRequest(
+                "A job without a download can't expect a SHA-256".to_string(),
+            ));
+        }
+
+        if self.keep_video && !self.audio_only {
+            return Err(YodelError::BadRequest(
+                "Keeping the video requires audio_only".to_string(),
+            ));
+        }
+
+        if let Some(quality) = &self.audio_quality {
+            if !self.audio_only {
+                return Err(YodelError::BadRequest(
+                    "An audio quality requires audio_only".to_string(),
+                ));
+            }
+            if !is_valid_audio_quality(quality) {
+                return Err(YodelError::BadRequest(format!(
+                    "Invalid audio quality: {}, expected 0 to 9 or a bitrate like 192K",
+                    quality
+                )));
+            }
+        }
+
+        if let Some(format) = &self.thumbnail_format {
+            if !self.write_thumbnail {
+                return Err(YodelError::BadRequest(
+                    "A thumbnail format requires write_thumbnail".to_string(),
+                ));
+            }
+
+            if !CONFIG.is_yt_dlp() {
+                return Err(YodelError::BadRequest(
+                    "Converting thumbnails requires yt-dlp".to_string(),
+                ));
+            }
+
+            if !THUMBNAIL_FORMATS.contains(&format.as_str()) {
+                return Err(YodelError::BadRequest(format!(
+                    "Unsupported thumbnail format: {}, expected one of: {}",
+                    format,
+                    THUMBNAIL_FORMATS.join(", ")
+                )));
+            }
+        }
+
+        if self.embed_info_json && !CONFIG.is_yt_dlp() {
+            return Err(YodelError::BadRequest(
+                "Embedding the info json requires yt-dlp".to_string(),
+            ));
+        }
+
+        if self.embed_chapters == Some(true) && !CONFIG.is_yt_dlp() {
+            return Err(YodelError::BadRequest(
+                "Embedding chapters requires yt-dlp".to_string(),
+            ));
+        }
+
+        if let Some(categories) = &self.sponsorblock_mark {
+            if !CONFIG.is_yt_dlp() {
+                return Err(YodelError::BadRequest(
+                    "Marking SponsorBlock segments requires yt-dlp".to_string(),
+                ));
+            }
+
+            if categories.is_empty() {
+                return Err(YodelError::BadRequest(
+                    "No SponsorBlock categories to mark".to_string(),
+                ));
+            }
+
+            if let Some(category) = categories
+                .iter()
+                .find(|category| !SPONSORBLOCK_CATEGORIES.contains(&category.as_str()))
+            {
+                return Err(YodelError::BadRequest(format!(
+                    "Unknown SponsorBlock category: {}, expected one of: {}",
+                    category,
+                    SPONSORBLOCK_CATEGORIES.join(", ")
+                )));
+            }
+        }
+
+        if self.subtitle_mode == SubtitleMode::Both && !CONFIG.is_yt_dlp() {
+            return Err(YodelError::BadRequest(
+                "Embedding subtitles and keeping the files requires yt-dlp".to_string(),
+            ));
+        }
+
+        if let Some(format) = &self.merge_format {
+            if self.audio_only {
+                return Err(YodelError::BadRequest(
+                    "A merge format can't be combined with audio_only".to_string(),
+                ));
+            }
+
+            if !MERGE_FORMATS.contains(&format.as_str()) {
+                return Err(YodelError::BadRequest(format!(
+                    "Unsupported merge format: {}, expected one of: {}",
+                    format,
+                    MERGE_FORMATS.join(", ")
+                )));
+            }
+        }
+
+        if let Some(remux) = &self.remux {
+            if !CONFIG.is_yt_dlp() {
+                return Err(YodelError::BadRequest(
+                    "Remuxing requires yt-dlp".to_string(),
+                ));
+            }
+
+            if !REMUX_FORMATS.contains(&remux.as_str()) {
+                return Err(YodelError::BadRequest(format!(
+                    "Unsupported remux format: {}, expected one of: {}",
+                    remux,
+                    REMUX_FORMATS.join(", ")
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// What to do with the subtitles of a video
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Hash, Eq, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SubtitleMode {
+    /// Embed the subtitles in the video file
+    #[default]
+    Embed,
+    /// Save the subtitles next to the video file
+    Sidecar,
+    /// Embed the subtitles and keep the subtitle files, yt-dlp only
+    Both,
+    /// Don't download subtitles
+    None,
+}
+
+impl SubtitleMode {
+    fn args(self) -> &'static [&'static str] {
+        match self {
+            SubtitleMode::Embed => &["--all-subs", "--embed-subs"],
+            SubtitleMode::Sidecar => &["--all-subs"],
+            // yt-dlp keeps the subtitle files when they are both written and embedded
+            SubtitleMode::Both => &["--all-subs", "--write-subs", "--embed-subs"],
+            SubtitleMode::None => &[],
+        }
+    }
+}
+
+/// The files youtube-dl wrote for a job
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct JobOutput {
+    /// The downloaded media file
+    path: Option<PathBuf>,
+    /// Extra files written next to the media file, like the description
+    sidecars: Vec<PathBuf>,
+    /// The video the audio was extracted from, when it was kept
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_video: Option<PathBuf>,
+    /// The thumbnail image, see `JobOptions::write_thumbnail`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thumbnail: Option<PathBuf>,
+    /// Size of the media file
+    size_bytes: Option<u64>,
+    /// The playlist was cut off after this many downloaded items, see `max_playlist_items`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated_after: Option<u32>,
+    /// SHA-256 of the media file, only computed when it's expected or `hash_downloads` is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+    /// A live stream was skipped, see `JobOptions::allow_live`
+    #[serde(skip)]
+    skipped_live: bool,
+    /// The video's metadata, only read for `metadata_only` jobs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<Metadata>,
+    /// The copies in the job's extra locations
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    copies: Vec<LocationCopy>,
+    /// Something worth knowing about a job that succeeded, eg: that there were no subtitles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+}
+
+/// The files of a job in one of its extra locations
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LocationCopy {
+    location: String,
+    paths: Vec<PathBuf>,
+    /// Why copying failed, some of the files might have been copied
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl JobOutput {
+    /// Collect the written files from youtube-dl's output
+    /// Relative paths are resolved against the directory youtube-dl ran in
+    fn parse(stdout: &str, directory: &Path) -> JobOutput {
+        let mut output = JobOutput::default();
+        let mut archived = 0;
+        // the downloaded media files, to count the items of a truncated playlist
+        let mut items: Vec<PathBuf> = Vec::new();
+
+        for line in stdout.lines() {
+            // strip the "[extractor] " prefix
+            let message = match line.find("] ") {
+                Some(index) if line.starts_with('[') => &line[index + 2..],
+                _ => continue,
+            };
+
+            if let Some(path) = message.strip_prefix("Destination: ") {
+                let path = directory.join(path);
+                if line.starts_with("[ExtractAudio]") {
+                    // the audio replaces the downloaded video as the media file
+                    output.source_video = output.path.take();
+                    items.pop();
+                    items.push(path.clone());
+                } else if !is_subtitle(&path) {
+                    items.push(path.clone());
+                }
+                output.path = Some(path);
+            } else if let Some(path) = message.strip_prefix("Merging formats into ") {
+                let path = directory.join(path.trim_matches('"'));
+                // the video and audio formats were downloaded as <name>.f<format id>.<ext>
+                let parts = path
+                    .file_stem()
+                    .map(|stem| format!("{}.f", stem.to_string_lossy()))
+                    .unwrap_or_default();
+                items.retain(|item| {
+                    !item
+                        .file_name()
+                        .is_some_and(|name| name.to_string_lossy().starts_with(&parts))
+                });
+                items.push(path.clone());
+                output.path = Some(path);
+            } else if let Some(path) = message
+                .strip_suffix(" has already been downloaded and merged")
+                .or_else(|| message.strip_suffix(" has already been downloaded"))
+            {
+                let path = directory.join(path);
+                items.push(path.clone());
+                output.path = Some(path);
+            } else if let Some(converted) = message.strip_prefix("Converting thumbnail \"") {
+                // Converting thumbnail "<path>" to <format>
+                if let Some(index) = converted.rfind("\" to ") {
+                    let path = directory.join(&converted[..index]);
+                    output.thumbnail = Some(path.with_extension(&converted[index + 5..]));
+                }
+            } else if message.contains("does not pass filter") && message.contains("!is_live") {
+                // youtube-dl prints the filter as is, yt-dlp in parentheses
+                output.skipped_live = true;
+            } else if message.ends_with("has already been recorded in the archive")
+                || message.ends_with("has already been recorded in archive")
+            {
+                archived += 1;
+            } else if message.starts_with("Maximum number of download") {
+                output.truncated_after = Some(items.len() as u32);
+            } else if message.starts_with("Writing ") {
+                if let Some(index) = message.find(" to: ") {
+                    let path = directory.join(&message[index + 5..]);
+                    if message.contains(" thumbnail ") {
+                        output.thumbnail = Some(path);
+                    } else {
+                        output.sidecars.push(path);
+                    }
+                }
+            }
+        }
+
+        // youtube-dl succeeds without downloading anything for archived videos
+        output.note = match (archived, &output.path) {
+            (0, _) => None,
+            (_, None) => {
+                Some("Already downloaded, it's recorded in the download archive".to_string())
+            }
+            (archived, Some(_)) => Some(format!(
+                "Skipped {} videos that are recorded in the download archive",
+                archived
+            )),
+        };
+
+        // embedded subtitles are written first and removed afterwards
+        output.sidecars.retain(|path| path.exists());
+        // the video is removed after extracting the audio, unless it's kept
+        output.source_video = output.source_video.filter(|path| path.exists());
+        output.thumbnail = output.thumbnail.filter(|path| path.exists());
+        output.size_bytes = output
+            .path
+            .as_ref()
+            .and_then(|path| fs::metadata(path).ok())
+            .map(|metadata| metadata.len());
+        output
+    }
+
+    /// Returns true if one of the sidecars is a subtitle file
+    fn has_subtitles(&self) -> bool {
+        self.sidecars.iter().any(|path| is_subtitle(path))
+    }
+
+    /// Read the metadata from the written .info.json file
+    fn read_metadata(&mut self) -> Result<(), String> {
+        let info_json = self
+            .sidecars
+            .iter()
+            .find(|path| path.to_string_lossy().ends_with(".info.json"))
+            .ok_or_else(|| "No metadata was written".to_string())?;
+
+        let metadata = formats::read_metadata(info_json)
+            .map_err(|e| format!("Unable to read the metadata: {}", e))?;
+        self.metadata = Some(metadata);
+        Ok(())
+    }
+
+    /// SHA-256 of the media file, in hex
+    fn hash(&self) -> std::io::Result<Option<String>> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+        Ok(Some(format!("{:x}", hasher.finalize())))
+    }
+
+    /// The media file, the sidecar files and the kept video
+    fn files(&self) -> impl Iterator<Item = &PathBuf> {
+        self.path
+            .iter()
+            .chain(self.sidecars.iter())
+            .chain(self.source_video.iter())
+            .chain(self.thumbnail.iter())
+    }
+
+    /// Move all files from `from` into `directory`
+    /// Fails without moving anything if a file is outside of `from` or would be overwritten
+    fn move_to(&self, from: &Path, directory: &Path) -> Result<JobOutput, YodelError> {
+        let target = |path: &PathBuf| -> Result<PathBuf, YodelError> {
+            confine(path, from)?;
+            let target = directory.join(path.file_name().unwrap_or_default());
+            if target.exists() {
+                return Err(YodelError::Conflict(target.display().to_string()));
+            }
+            Ok(target)
+        };
+
+        // check every file before moving any of them
+        for path in self.files() {
+            target(path)?;
+        }
+
+        let move_file = |path: &PathBuf| -> Result<PathBuf, YodelError> {
+            let target = target(path)?;
+            // renaming fails across filesystems
+            if fs::rename(path, &target).is_err() {
+                fs::copy(path, &target)?;
+                fs::remove_file(path)?;
+            }
+            Ok(target)
+        };
+
+        Ok(JobOutput {
+            path: self.path.as_ref().map(move_file).transpose()?,
+            sidecars: self
+                .sidecars
+                .iter()
+                .map(move_file)
+                .collect::<Result<_, _>>()?,
+            source_video: self.source_video.as_ref().map(move_file).transpose()?,
+            thumbnail: self.thumbnail.as_ref().map(move_file).transpose()?,
+            size_bytes: self.size_bytes,
+            truncated_after: self.truncated_after,
+            sha256: self.sha256.clone(),
+            skipped_live: self.skipped_live,
+            metadata: self.metadata.clone(),
+            copies: self.copies.clone(),
+            note: self.note.clone(),
+        })
+    }
+
+    /// Hard link or copy all files from `from` into the locations
+    /// A failure only affects the location it happened for
+    fn copy_to_locations(&self, from: &Path, locations: &[Location]) -> Vec<LocationCopy> {
+        locations
+            .iter()
+            // an approved job can be moved to one of its extra locations
+            .filter(|location| location.path() != from)
+            .map(|location| match self.copy_to(from, location.path()) {
+                Ok(paths) => LocationCopy {
+                    location: location.name().to_string(),
+                    paths,
+                    error: None,
+                },
+                Err(e) => {
+                    error!("unable to copy to {}: {}", location.name(), e);
+                    LocationCopy {
+                        location: location.name().to_string(),
+                        paths: Vec::new(),
+                        error: Some(e.to_string()),
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Hard link or copy all files from `from` into `directory`
+    /// Fails without copying anything if a file is outside of `from` or would be overwritten
+    fn copy_to(&self, from: &Path, directory: &Path) -> Result<Vec<PathBuf>, YodelError> {
+        let target = |path: &PathBuf| -> Result<PathBuf, YodelError> {
+            confine(path, from)?;
+            let target = directory.join(path.file_name().unwrap_or_default());
+            if target.exists() {
+                return Err(YodelError::Conflict(target.display().to_string()));
+            }
+            Ok(target)
+        };
+
+        // check every file before copying any of them
+        for path in self.files() {
+            target(path)?;
+        }
+
+        self.files()
+            .map(|path| -> Result<PathBuf, YodelError> {
+                let target = target(path)?;
+                // hard links fail across filesystems
+                if fs::hard_link(path, &target).is_err() {
+                    fs::copy(path, &target)?;
+                }
+                Ok(target)
+            })
+            .collect()
+    }
+
+    /// Remove all files, only files inside of `from` are removed
+    fn remove(&self, from: &Path) -> Result<(), YodelError> {
+        for path in self.files() {
+            confine(path, from)?;
+            fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A youtube-dl process that can be killed from outside of the thread that runs it
+#[derive(Default)]
+struct Download {
+    state: Mutex<DownloadState>,
+}
+
+#[derive(Default)]
+struct DownloadState {
+    /// Only set while youtube-dl is running
+    child: Option<Child>,
+    cancelled: bool,
+}
+
+impl Download {
+    /// Kill youtube-dl, or prevent it from running when it hasn't started yet
+    fn cancel(&self) {
+        let mut state = self.state.lock().expect("download lock poisoned");
+        state.cancelled = true;
+        if let Some(child) = &mut state.child {
+            kill(child);
+        }
+    }
+
+    /// Returns true if the download was cancelled or paused
+    fn is_cancelled(&self) -> bool {
+        self.state.lock().expect("download lock poisoned").cancelled
+    }
+
+    /// The process id of youtube-dl, while it's running
+    fn pid(&self) -> Option<u32> {
+        let state = self.state.lock().expect("download lock poisoned");
+        state.child.as_ref().map(Child::id)
+    }
+}
+
+/// Download a job with youtube-dl, the returned response tells how it went
+/// This blocks until youtube-dl exits, don't call it on an actix thread
+fn download_job(mut job: Job, download: &Download, addr: &Addr<JobServer>) -> JobResponse {
+    if let Some(min_free_space) = CONFIG.min_free_space {
+        if let Err(reason) = min_free_space.check(job.download_dir()) {
+            error!("refusing to start job: {}", reason);
+            return JobResponse::Failed { job, reason };
+        }
+    }
+
+    if job.options.embed_info_json && job.options.remux.as_deref() != Some("mkv") {
+        // yt-dlp skips the embedding for other containers
+        warn!("only mkv files can embed the info json: {}", job.url);
+    }
+
+    if job.options.write_comments {
+        warn!("fetching comments can take a long time: {}", job.url);
+    }
+
+    let mut command = CONFIG.downloader_command();
+    command
+        .current_dir(job.download_dir())
+        .args(build_args(&job));
+
+    let id = job.id;
+    let attempt = job.attempt;
+    let directory = job.download_dir().to_path_buf();
+    let res = run_download(
+        &mut command,
+        download,
+        |progress| {
+            addr.do_send(JobProgress {
+                id,
+                attempt,
+                progress,
+            })
+        },
+        |path| {
+            let path = directory.join(path);
+            addr.do_send(JobDestination { id, attempt, path });
+        },
+    );
+
+    debug!("finished");
+    let output = match res {
+        Ok(output) => output,
+        Err(reason) => {
+            // this is a server error
+            error!("job startup failed: {}", reason);
+            return JobResponse::Failed {
+                job,
+                reason: reason.to_string(),
+            };
+        }
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let job_output = JobOutput::parse(&stdout, job.download_dir());
+    let truncated = job_output.truncated_after.is_some()
+        && output.status.code() == Some(MAX_DOWNLOADS_EXIT_CODE);
+
+    if job_output.skipped_live && job_output.path.is_none() {
+        let reason = "This is a live stream, set allow_live to record it".to_string();
+        error!("{}: {}", job, reason);
+        return JobResponse::Failed { job, reason };
+    }
+
+    if !output.status.success() && !truncated {
+        // a paused download is continued from its partial files
+        if CONFIG.cleanup_on_failure && !download.is_cancelled() {
+            remove_partial_files(&stdout, job.download_dir());
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        error!("youtube-dl failed: {:?}", stderr);
+        let reason = failure_reason(&stderr);
+        return JobResponse::Failed { job, reason };
+    }
+
+    info!("job succeeded!");
+    job.output = job_output;
+    if job.options.subtitles_only && !job.output.has_subtitles() {
+        info!("{} has no subtitles", job);
+        job.output.note = Some("The video has no subtitles".to_string());
+    }
+    if job.options.metadata_only {
+        if let Err(reason) = job.output.read_metadata() {
+            error!("{}: {}", job, reason);
+            return JobResponse::Failed { job, reason };
+        }
+    }
+    if let Err(reason) = job.verify_checksum() {
+        error!("{}: {}", job, reason);
+        return JobResponse::Failed { job, reason };
+    }
+    // staged jobs are copied once they're approved
+    if !job.staged {
+        job.output.copies = job
+            .output
+            .copy_to_locations(job.location.path(), &job.extra_locations);
+    }
+    JobResponse::Finished(job)
+}
+
+/// Kill youtube-dl and the programs it started, like ffmpeg
+fn kill(child: &mut Child) {
+    // youtube-dl leads its own process group, see `run_download`
+    #[cfg(unix)]
+    {
+        let group = -(child.id() as libc::pid_t);
+        if unsafe { libc::kill(group, libc::SIGKILL) } == 0 {
+            return;
+        }
+    }
+
+    let _ = child.kill();
+}
+
+/// Run a youtube-dl download, calling `on_progress` for every progress update
+/// and `on_destination` with every file youtube-dl starts downloading
+/// The progress lines are left out of the returned stdout
+fn run_download<F, D>(
+    command: &mut Command,
+    download: &Download,
+    on_progress: F,
+    on_destination: D,
+) -> std::io::Result<Output>
+where
+    F: Fn(Progress),
+    D: Fn(&str),
+{
+    // start youtube-dl in a new process group, so cancelling can kill ffmpeg along with it
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // read stderr on its own thread, a full stderr pipe would block youtube-dl
+    let mut stderr_pipe = child.stderr.take().expect("stderr is piped");
+    let stderr = std::thread::spawn(move || {
+        let mut stderr = Vec::new();
+        let _ = stderr_pipe.read_to_end(&mut stderr);
+        stderr
+    });
+
+    let stdout_pipe = child.stdout.take().expect("stdout is piped");
+    {
+        let mut state = download.state.lock().expect("download lock poisoned");
+        if state.cancelled {
+            kill(&mut child);
+        }
+        // killing youtube-dl closes its output, which ends the loop below
+        state.child = Some(child);
+    }
+
+    let mut stdout = Vec::new();
+    let reader = BufReader::new(stdout_pipe);
+    for line in reader.split(b'\n') {
+        let line = line?;
+        let text = String::from_utf8_lossy(&line);
+        match Progress::parse(&text) {
+            Some(progress) => on_progress(progress),
+            None => {
+                if let Some(path) = text.strip_prefix("[download] Destination: ") {
+                    on_destination(path);
+                }
+                stdout.extend_from_slice(&line);
+                stdout.push(b'\n');
+            }
+        }
+    }
+
+    let mut child = download
+        .state
+        .lock()
+        .expect("download lock poisoned")
+        .child
+        .take()
+        .expect("the child is stored before reading its output");
+    let status = child.wait()?;
+    let stderr = stderr.join().unwrap_or_default();
+
+    Ok(Output {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+/// Returns true if the file is a subtitle file, going by its extension
+fn is_subtitle(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| SUBTITLE_FORMATS.contains(&extension))
+}
+
+/// Remove the partial files of the destinations in youtube-dl's output
+/// Only files named after a destination with a partial download suffix are removed
+fn remove_partial_files(stdout: &str, directory: &Path) {
+    let destinations = stdout
+        .lines()
+        .filter_map(|line| line.strip_prefix("[download] Destination: "))
+        .map(|path| directory.join(path));
+
+    for destination in destinations {
+        let (parent, name) = match (destination.parent(), destination.file_name()) {
+            (Some(parent), Some(name)) => (parent, name.to_string_lossy()),
+            _ => continue,
+        };
+        let entries = match fs::read_dir(parent) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("unable to look for partial files in {:?}: {}", parent, e);
+                continue;
+            }
+        };
+
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            // eg: video.mp4.part, video.mp4.part-Frag12 or video.mp4.ytdl
+            let is_partial = file_name
+                .to_string_lossy()
+                .strip_prefix(name.as_ref())
+                .is_some_and(|suffix| suffix.starts_with(".part") || suffix == ".ytdl");
+            if !is_partial {
+                continue;
+            }
+
+            let path = entry.path();
+            if confine(&path, directory).is_err() {
+                continue;
+            }
+            match fs::remove_file(&path) {
+                Ok(()) => info!("removed partial file {:?}", path),
+                Err(e) => warn!("unable to remove partial file {:?}: {}", path, e),
+            }
+        }
+    }
+}
+
+/// Tell the configured notification channels that a job completed
+fn notify_completed(job: &Job) {
+    if let Some(telegram) = &CONFIG.telegram {
+        telegram.notify(job);
+    }
+    if let Some(notification) = &CONFIG.desktop_notification {
+        notification.notify(job);
+    }
+    webhook::notify(job);
+}
+
+/// Returns true for comma separated item numbers and ranges, eg: 1-3,7,10-13
+fn is_valid_playlist_items(items: &str) -> bool {
+    let is_number = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+
+    items.split(',').all(|item| {
+        let mut bounds = item.trim().splitn(2, '-');
+        let start = bounds.next().unwrap_or_default();
+        match bounds.next() {
+            Some(end) => is_number(start) && is_number(end),
+            None => is_number(start),
+        }
+    })
+}
+
+/// Returns true for a VBR quality from 0 to 9, or a bitrate in kilobits, eg: 192K
+fn is_valid_audio_quality(quality: &str) -> bool {
+    let is_number = |part: &str| {
+        !part.is_empty() && part.len() <= 4 && part.chars().all(|c| c.is_ascii_digit())
+    };
+
+    match quality
+        .strip_suffix('K')
+        .or_else(|| quality.strip_suffix('k'))
+    {
+        Some(bitrate) => is_number(bitrate),
+        None => quality.len() == 1 && is_number(quality),
+    }
+}
+
+/// The youtube-dl arguments for a job, ending with its url
+fn build_args(job: &Job) -> Vec<String> {
+    let options = &job.options;
+    let mut args: Vec<String> = vec![
+        // print every progress update on its own line
+        "--newline".to_string(),
+        "--no-overwrite".to_string(),
+        // pick up the .part file of a paused or interrupted download
+        "--continue".to_string(),
+    ];
+    if options.subtitles_only {
+        // the youtube-dl spelling, yt-dlp accepts it too
+        args.push("--write-sub".to_string());
+        match &options.subtitle_langs {
+            Some(langs) => {
+                args.push("--sub-lang".to_string());
+                args.push(langs.replace(' ', ""));
+            }
+            None => args.push("--all-subs".to_string()),
+        }
+    } else if !options.audio_only {
+        // audio files can't hold subtitles, downloading them would be a waste
+        args.extend(
+            options
+                .subtitle_mode
+                .args()
+                .iter()
+                .map(|arg| arg.to_string()),
+        );
+    }
+    args.push("-o".to_string());
+    args.push(job.output_template.clone());
+
+    // the height limit doesn't matter when only the audio is kept
+    let format = if options.audio_only {
+        options.format_id.clone()
+    } else {
+        formats::select_format(options.format_id.as_deref(), CONFIG.max_height)
+    };
+    if let Some(format) = format {
+        args.push("-f".to_string());
+        args.push(format);
+    }
+
+    if let Some(categories) = &options.sponsorblock_mark {
+        args.push("--sponsorblock-mark".to_string());
+        args.push(categories.join(","));
+    }
+
+    if let Some(format) = &options.merge_format {
+        args.push("--merge-output-format".to_string());
+        args.push(format.clone());
+    }
+
+    if let Some(remux) = &options.remux {
+        args.push("--remux-video".to_string());
+        args.push(remux.clone());
+    }
+
+    if let Some(items) = &options.playlist_items {
+        args.push("--playlist-items".to_string());
+        args.push(items.clone());
+    }
+
+    if let Some(archive) = &CONFIG.download_archive {
+        args.push("--download-archive".to_string());
+        args.push(archive.display().to_string());
+    }
+
+    if let Some(max) = CONFIG.max_playlist_items {
+        args.push("--max-downloads".to_string());
+        args.push(max.to_string());
+    }
+
+    // live streams are downloaded until they end, which can take forever
+    if !options.allow_live {
+        args.push("--match-filter".to_string());
+        args.push("!is_live".to_string());
+    }
+
+    if options.audio_only {
+        args.push("--extract-audio".to_string());
+        if let Some(quality) = &options.audio_quality {
+            args.push("--audio-quality".to_string());
+            args.push(quality.clone());
+        }
+        if options.keep_video {
+            args.push("--keep-video".to_string());
+        }
+    }
+
+    args.extend(options.lookup_args());
+
+    if let Some(retries) = options.retries {
+        args.push("--retries".to_string());
+        args.push(retries.to_string());
+        args.push("--fragment-retries".to_string());
+        args.push(retries.to_string());
+    }
+
+    if options.embed_chapters == Some(true) {
+        args.push("--embed-chapters".to_string());
+    }
+
+    // the sanitized file name is picked up from youtube-dl's output, see `JobOutput::parse`
+    if options.restrict_filenames == Some(true) {
+        args.push("--restrict-filenames".to_string());
+    }
+
+    if options.prefer_free_formats == Some(true) {
+        args.push("--prefer-free-formats".to_string());
+    }
+
+    if options.embed_info_json {
+        args.push("--embed-info-json".to_string());
+    }
+
+    if options.write_description {
+        args.push("--write-description".to_string());
+    }
+
+    // sites without a thumbnail only make youtube-dl print a warning
+    if options.write_thumbnail {
+        args.push("--write-thumbnail".to_string());
+        if let Some(format) = &options.thumbnail_format {
+            args.push("--convert-thumbnails".to_string());
+            args.push(format.clone());
+        }
+    }
+
+    if options.write_comments {
+        // yt-dlp stores the comments in the info json
+        args.push("--write-comments".to_string());
+    }
+
+    if options.metadata_only || options.subtitles_only {
+        args.push("--skip-download".to_string());
+    }
+
+    if options.write_comments || options.metadata_only {
+        args.push("--write-info-json".to_string());
+    }
+
+    args.push(job.url.clone());
+    args
+}
+
+/// Replace the values of the arguments in `SECRET_ARGS`, given as `--arg value` or `--arg=value`
+fn redact(args: Vec<String>) -> Vec<String> {
+    let mut redact_next = false;
+    args.into_iter()
+        .map(|arg| {
+            let redacted = if redact_next {
+                "<redacted>".to_string()
+            } else {
+                match arg.split_once('=') {
+                    Some((name, _)) if SECRET_ARGS.contains(&name) => {
+                        format!("{}=<redacted>", name)
+                    }
+                    _ => arg.clone(),
+                }
+            };
+            redact_next = SECRET_ARGS.contains(&arg.as_str());
+            redacted
+        })
+        .collect()
+}
+
+/// Describe why youtube-dl failed based on its error output
+pub(crate) fn failure_reason(stderr: &str) -> String {
+    KNOWN_FAILURES
+        .iter()
+        .find(|(pattern, _)| stderr.contains(pattern))
+        .map(|(_, reason)| reason.to_string())
+        .unwrap_or_else(|| stderr.to_string())
+}
+
+/// The path of the options file with this name, names can't point outside of `directory`
+fn find_config_file(name: &str, directory: &Path) -> Option<PathBuf> {
+    let is_special = name.is_empty() || name.contains("..");
+    if is_special || name.contains(['/', '\\', '\0']) {
+        return None;
+    }
+
+    let directory = directory.canonicalize().ok()?;
+    let path = directory.join(name).canonicalize().ok()?;
+    if path.starts_with(&directory) && path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Fails if `path` isn't inside of `directory`
+fn confine(path: &Path, directory: &Path) -> Result<(), YodelError> {
+    if path.canonicalize()?.starts_with(directory.canonicalize()?) {
+        Ok(())
+    } else {
+        error!("refusing to touch {:?}, it's not in {:?}", path, directory);
+        Err(YodelError::BadRequest(format!(
+            "{} is outside of {}",
+            path.display(),
+            directory.display()
+        )))
+    }
+}
+
+impl Job {
+    pub(crate) fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub(crate) fn started_on(&self) -> DateTime<Utc> {
+        self.started_on
+    }
+
+    pub(crate) fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub(crate) fn status(&self) -> &JobStatus {
+        &self.status
+    }
+
+    pub(crate) fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub(crate) fn location(&self) -> &Location {
+        &self.location
+    }
+
+    pub(crate) fn key(&self) -> JobKey {
+        JobKey {
+            url: self.url.clone(),
+            location: self.location.clone(),
+            options: self.options.clone(),
+        }
+    }
+
+    pub(crate) fn in_progress(&self) -> bool {
+        self.status == JobStatus::InProgress
+    }
+
+    pub(crate) fn is_queued(&self) -> bool {
+        self.status == JobStatus::Queued
+    }
+
+    pub(crate) fn has_failed(&self) -> bool {
+        matches!(self.status, JobStatus::Failed(_))
+    }
+
+    pub(crate) fn has_succeeded(&self) -> bool {
+        self.status == JobStatus::Finished
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.status == JobStatus::Paused
+    }
+
+    /// return all completed jobs, failed or not
+    pub(crate) fn is_completed(&self) -> bool {
+        !self.in_progress() && !self.is_queued() && !self.is_paused()
+    }
+
+    /// The host of the job's url, if it can be parsed
+    pub(crate) fn host(&self) -> Option<String> {
+        let url = url::Url::parse(&self.url).ok()?;
+        url.host_str().map(String::from)
+    }
+
+    pub(crate) fn set_in_progress(&mut self) {
+        self.status = JobStatus::InProgress;
+        self.attempt += 1;
+    }
+
+    pub(crate) fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// The last progress is kept, to show how far the download got
+    pub(crate) fn set_paused(&mut self) {
+        self.status = JobStatus::Paused;
+    }
+
+    pub(crate) fn resume(&mut self) {
+        self.status = JobStatus::Queued;
+    }
+
+    pub(crate) fn awaiting_review(&self) -> bool {
+        self.status == JobStatus::AwaitingReview
+    }
+
+    /// Hash the downloaded file when needed and compare it to the expected hash
+    /// This reads the whole file, don't call it on an actix thread
+    fn verify_checksum(&mut self) -> Result<(), String> {
+        if self.options.expected_sha256.is_none() && !CONFIG.hash_downloads {
+            return Ok(());
+        }
+
+        self.output.sha256 = self
+            .output
+            .hash()
+            .map_err(|e| format!("Unable to hash the downloaded file: {}", e))?;
+
+        match (&self.options.expected_sha256, &self.output.sha256) {
+            (Some(expected), Some(actual)) if expected != actual => Err(format!(
+                "Checksum mismatch, expected {} but got {}",
+                expected, actual
+            )),
+            (Some(_), None) => Err("Checksum mismatch, no file was downloaded".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    /// The directory youtube-dl downloads to
+    fn download_dir(&self) -> &Path {
+        match &CONFIG.staging {
+            Some(staging) if self.staged => staging,
+            _ => self.location.path(),
+        }
+    }
+
+    /// Staged jobs have to be reviewed after they're downloaded
+    pub(crate) fn set_finished(&mut self, output: JobOutput) {
+        self.progress = None;
+        if self.title.is_none() {
+            self.title = output
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.title.clone());
+        }
+        self.status = if self.staged {
+            JobStatus::AwaitingReview
+        } else {
+            JobStatus::Finished
+        };
+        self.output = output;
+    }
+
+    pub(crate) fn approve(&mut self, location: Location, output: JobOutput) {
+        self.status = JobStatus::Finished;
+        self.location = location;
+        self.output = output;
+    }
+
+    pub(crate) fn reject(&mut self) {
+        self.status = JobStatus::Failed("Rejected during review".to_string());
+        self.output = JobOutput::default();
+    }
+
+    pub(crate) fn set_failed(&mut self, reason: String) {
+        self.progress = None;
+        self.status = JobStatus::Failed(reason);
+    }
+
+    /// Queue a failed job again
+    pub(crate) fn retry(&mut self) {
+        self.status = JobStatus::Queued;
+        self.output = JobOutput::default();
+        self.progress = None;
+    }
+
+    /// Retry with other options, see `JobOptions::merge`
+    pub(crate) fn retry_with(&mut self, options: JobOptions) {
+        self.output_template = options.output_template();
+        self.options = options;
+        self.retry();
+    }
+
+    /// Returns true if the job failed for a reason that retrying won't fix
+    fn failed_permanently(&self) -> bool {
+        match &self.status {
+            JobStatus::Failed(reason) => PERMANENT_FAILURES
+                .iter()
+                .any(|pattern| reason.contains(pattern)),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn set_progress(&mut self, progress: Progress) {
+        self.progress = Some(progress);
+    }
+
+    pub(crate) fn set_destination(&mut self, destination: PathBuf) {
+        self.destination = Some(destination);
+    }
+
+    pub(crate) fn set_title(&mut self, title: String) {
+        self.title = Some(title);
+    }
+
+    /// Fails when the directory of one of the job's locations doesn't exist,
+    /// unless it can be created, see `Config::create_missing_dirs`
+    fn check_directories(&self, create: bool) -> Result<(), YodelError> {
+        for location in std::iter::once(&self.location).chain(&self.extra_locations) {
+            let path = location.path();
+            if path.is_dir() {
+                continue;
+            }
+
+            if !create {
+                return Err(YodelError::BadRequest(format!(
+                    "The directory of location {} doesn't exist: {}",
+                    location.name(),
+                    path.display()
+                )));
+            }
+
+            // the request is fine, the server can't write to its own location
+            fs::create_dir_all(path).map_err(|e| {
+                error!(
+                    "unable to create the directory of location {}: {}",
+                    location.name(),
+                    e
+                );
+                YodelError::InternalServerError
+            })?;
+            info!(
+                "created the directory of location {}: {}",
+                location.name(),
+                path.display()
+            );
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+}
+
+#[cfg(test)]
+impl Job {
+    /// Have the job reviewed once it's downloaded
+    pub(crate) fn stage(&mut self) {
+        self.staged = true;
+    }
+
+    /// A queued job for the url with the default options, in a location that isn't configured
+    pub(crate) fn for_test(url: &str) -> Job {
+        let options = JobOptions::default();
+        Job {
+            id: Uuid::new_v4(),
+            url: url.to_string(),
+            title: None,
+            location: Location::new("test", &std::env::temp_dir()),
+            extra_locations: Vec::new(),
+            tags: Vec::new(),
+            started_on: Utc::now(),
+            status: JobStatus::Queued,
+            output_template: options.output_template(),
+            options,
+            output: JobOutput::default(),
+            staged: false,
+            progress: None,
+            destination: None,
+            attempt: 0,
+        }
+    }
+}
+
+/// Lowercase the tags and remove the empty and duplicate ones
+fn normalize_tags(requested: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in requested {
+        let tag = tag.trim().to_lowercase();
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+impl TryFrom<JobRequest> for Job {
+    type Error = YodelError;
+
+    fn try_from(request: JobRequest) -> Result<Job, Self::Error> {
+        let name = request
+            .location
+            .as_deref()
+            .filter(|name| !name.is_empty())
+            .or_else(|| CONFIG.route(&request.url))
+            .or_else(|| CONFIG.default_location.as_deref())
+            .ok_or_else(|| YodelError::BadRequest("No location given".to_string()))?;
+
+        let location = match Location::lookup(name) {
+            Some(location) => location,
+            None => {
+                return Err(YodelError::BadRequest("Invalid Location".to_string()));
+            }
+        };
+
+        let mut extra_locations: Vec<Location> = Vec::new();
+        for name in request.locations.iter().flatten() {
+            let extra = Location::lookup(name)
+                .ok_or_else(|| YodelError::BadRequest(format!("Invalid Location: {}", name)))?;
+            if extra != location && !extra_locations.contains(&extra) {
+                extra_locations.push(extra);
+            }
+        }
+
+        CONFIG.check_domain(&request.url)?;
+        let options = request.options.with_source(&request.url);
+        options.validate()?;
+        let options = options.with_defaults();
+        // there's nothing to review without a download
+        let staged = CONFIG.staging.is_some() && !options.metadata_only && !options.subtitles_only;
+
+        let id = if CONFIG.deterministic_ids {
+            content_id(&request.url, &location, &options)
+        } else {
+            Uuid::new_v4()
+        };
+
+        Ok(Job {
+            id,
+            url: request.url,
+            title: None,
+            location,
+            extra_locations,
+            tags: normalize_tags(request.tags.unwrap_or_default()),
+            started_on: Utc::now(),
+            status: JobStatus::Queued,
+            output_template: options.output_template(),
+            options,
+            output: JobOutput::default(),
+            staged,
+            progress: None,
+            destination: None,
+            attempt: 0,
+        })
+    }
+}
+
+/// A job id derived from the job's identity, the same request always gets the same id
+/// The options include the configured defaults, so changing those changes the ids too
+/// A job keeps its id when it's retried with other options, a new request with its old
+/// options is then rejected as a conflict instead of getting a job of its own
+fn content_id(url: &str, location: &Location, options: &JobOptions) -> Uuid {
+    let identity = serde_json::json!([url, location.name(), options]);
+    Uuid::new_v5(&Uuid::NAMESPACE_URL, identity.to_string().as_bytes())
+}
+
+/// The identity of a job, two jobs with the same key are duplicates
+#[derive(Hash, Eq, PartialEq, Clone, Debug)]
+pub(crate) struct JobKey {
+    url: String,
+    location: Location,
+    options: JobOptions,
+}
+
+impl Hash for Job {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+impl PartialEq for Job {
+    fn eq(&self, other: &Self) -> bool {
+        self.key().eq(&other.key())
+    }
+}
+
+impl Eq for Job {}
+
+impl fmt::Display for Job {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(title) = &self.title {
+            write!(f, "{}", title)
+        } else {
+            write!(f, "{}", self.url)
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct JobRequest {
+    url: String,
+    /// Defaults to the configured default location
+    location: Option<String>,
+    /// Extra locations the download is copied to
+    locations: Option<Vec<String>>,
+    /// Free-form labels, see `Job::tags`
+    tags: Option<Vec<String>>,
+    #[serde(flatten)]
+    options: JobOptions,
+}
+
+/// What happened to a submitted job
+enum Submission {
+    /// The job was added to the queue
+    Created(Job),
+    /// The same job was already queued, it was moved to the front of the queue instead
+    Bumped(Job),
+}
+
+/// Turn a request into a job, creating the directories of its locations when configured
+/// This touches the filesystem, don't call it on an actix thread
+fn prepare_job(request: JobRequest) -> Result<Job, YodelError> {
+    request.options.check_allowed()?;
+    let job = Job::try_from(request)?;
+    job.check_directories(CONFIG.create_missing_dirs)?;
+    Ok(job)
+}
+
+/// Add a job that was prepared with `prepare_job`
+#[derive(Message)]
+#[rtype(result = "Result<Submission, YodelError>")]
+struct AddJob {
+    job: Job,
+}
+
+impl Handler<AddJob> for JobServer {
+    type Result = Result<Submission, YodelError>;
+
+    fn handle(&mut self, msg: AddJob, ctx: &mut Context<Self>) -> Self::Result {
+        let mut job = msg.job;
+        debug!("adding {:?}", job);
+
+        if self.draining {
+            return Err(YodelError::ServiceUnavailable(
+                "The server is shutting down".to_string(),
+            ));
+        }
+
+        if CONFIG.bump_duplicates {
+            if let Some(id) = self.store.queued_duplicate(&job.key()) {
+                self.store.reorder(&[id]);
+                self.notify_waiters();
+                let job = self
+                    .store
+                    .get(&id)
+                    .cloned()
+                    .ok_or_else(|| YodelError::NotFound(format!("job {}", id)))?;
+                info!("moved {} to the front of the queue", job);
+                return Ok(Submission::Bumped(job));
+            }
+        }
+
+        // the actor handles one request at a time, so of two identical requests the second
+        // always fails here, before any youtube-dl process is spawned for it
+        self.store.add(job.clone())?;
+
+        if CONFIG.fetch_titles {
+            match self.titles.get(&job.url) {
+                Some(title) => {
+                    debug!("using the cached title of {}", job.url);
+                    if let Some(titled) = self.store.set_title(job.id, title) {
+                        job = titled;
+                    }
+                }
+                None => self.search_title(job.clone(), ctx.address()),
+            }
+        }
+        self.dispatch(ctx);
+        self.broadcast_changes();
+        self.notify_waiters();
+        Ok(Submission::Created(job))
+    }
+}
+
+/// youtube-dl of a download exited and its outcome was sent
+#[derive(Message)]
+#[rtype(result = "()")]
+struct DownloadEnded {
+    id: Uuid,
+    download: Arc<Download>,
+}
+
+impl Handler<DownloadEnded> for JobServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: DownloadEnded, ctx: &mut Context<Self>) -> Self::Result {
+        let current = self
+            .downloads
+            .get(&msg.id)
+            .is_some_and(|download| Arc::ptr_eq(download, &msg.download));
+        if current {
+            self.downloads.remove(&msg.id);
+        }
+        // a resumed or retried job waits for this
+        self.dispatch(ctx);
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct JobProgress {
+    id: Uuid,
+    /// See `Job::attempt`
+    attempt: u32,
+    progress: Progress,
+}
+
+impl Handler<JobProgress> for JobServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: JobProgress, ctx: &mut Context<Self>) -> Self::Result {
+        if !self.store.is_current_attempt(&msg.id, msg.attempt) {
+            return;
+        }
+
+        let job = match self.store.set_progress(msg.id, msg.progress) {
+            Some(job) => job,
+            None => return,
+        };
+
+        let interval = Duration::from_millis(CONFIG.progress_interval);
+        let complete = job
+            .progress
+            .as_ref()
+            .is_some_and(|progress| progress.percent >= 100.0);
+
+        match self.last_progress.get(&msg.id) {
+            Some(last) if !complete && last.elapsed() < interval => {
+                // the store keeps the latest progress, it's sent once the interval is over
+                if !self.progress_timers.contains_key(&msg.id) {
+                    let id = msg.id;
+                    let wait = interval.checked_sub(last.elapsed()).unwrap_or_default();
+                    let handle = ctx.run_later(wait, move |act, ctx| {
+                        act.progress_timers.remove(&id);
+                        if let Some(job) = act.store.get(&id).filter(|job| job.in_progress()) {
+                            let job = job.clone();
+                            act.send_progress(job, ctx);
+                        }
+                    });
+                    self.progress_timers.insert(id, handle);
+                }
+            }
+            _ => self.send_progress(job, ctx),
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct JobDestination {
+    id: Uuid,
+    /// See `Job::attempt`
+    attempt: u32,
+    path: PathBuf,
+}
+
+impl Handler<JobDestination> for JobServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: JobDestination, _: &mut Context<Self>) -> Self::Result {
+        if self.store.is_current_attempt(&msg.id, msg.attempt) {
+            self.store.set_destination(msg.id, msg.path);
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct VideoTitle {
+    job: Job,
+    title: String,
+}
+
+impl Handler<VideoTitle> for JobServer {
+    type Result = ();
+
+    fn handle(&mut self, video_title: VideoTitle, _: &mut Context<Self>) -> Self::Result {
+        self.titles
+            .insert(video_title.job.url.clone(), video_title.title.clone());
+
+        // the job might have been removed while the title lookup was running
+        if self
+            .store
+            .set_title(video_title.job.id(), video_title.title)
+            .is_none()
+        {
+            debug!("ignoring title for unknown job: {}", video_title.job);
+            return;
+        }
+
+        self.broadcast_changes();
+        self.notify_waiters();
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<Job, YodelError>")]
+pub(crate) struct GetJob {
+    pub(crate) id: Uuid,
+}
+
+impl Handler<GetJob> for JobServer {
+    type Result = Result<Job, YodelError>;
+
+    fn handle(&mut self, msg: GetJob, _: &mut Context<Self>) -> Self::Result {
+        self.store
+            .get(&msg.id)
+            .cloned()
+            .ok_or_else(|| YodelError::NotFound(format!("job {}", msg.id)))
+    }
+}
+
+/// Check that a job can be approved or rejected, and to which location
+#[derive(Message)]
+#[rtype(result = "Result<Job, YodelError>")]
+struct PrepareReview {
+    id: Uuid,
+    location: Option<Location>,
+}
+
+impl Handler<PrepareReview> for JobServer {
+    type Result = Result<Job, YodelError>;
+
+    fn handle(&mut self, msg: PrepareReview, _: &mut Context<Self>) -> Self::Result {
+        let mut job = self
+            .store
+            .get(&msg.id)
+            .cloned()
+            .ok_or_else(|| YodelError::NotFound(format!("job {}", msg.id)))?;
+
+        if !job.awaiting_review() {
+            return Err(YodelError::BadRequest(format!(
+                "{} is not awaiting review",
+                job
+            )));
+        }
+
+        if let Some(location) = msg.location {
+            job.location = location;
+            if self.store.is_duplicate(job.id, &job.key()) {
+                return Err(YodelError::Conflict(job.to_string()));
+            }
+        }
+
+        Ok(job)
+    }
+}
+
+enum Review {
+    /// The files were moved to the location
+    Approved {
+        location: Location,
+        output: Box<JobOutput>,
+    },
+    /// The files were removed
+    Rejected,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<Job, YodelError>")]
+struct ReviewJob {
+    id: Uuid,
+    review: Review,
+}
+
+impl Handler<ReviewJob> for JobServer {
+    type Result = Result<Job, YodelError>;
+
+    fn handle(&mut self, msg: ReviewJob, _: &mut Context<Self>) -> Self::Result {
+        // another request might have reviewed the job while its files were moved or removed
+        let job = match msg.review {
+            Review::Approved { location, output } => self.store.approve(msg.id, location, *output),
+            Review::Rejected => self.store.reject(msg.id),
+        }?;
+
+        self.broadcast_changes();
+        self.notify_waiters();
+        Ok(job)
+    }
+}
+
+/// Reorder the queue, see `JobStore::reorder`
+#[derive(Message)]
+#[rtype(result = "Vec<Job>")]
+struct ReorderQueue {
+    ids: Vec<Uuid>,
+}
+
+impl Handler<ReorderQueue> for JobServer {
+    type Result = MessageResult<ReorderQueue>;
+
+    fn handle(&mut self, msg: ReorderQueue, _: &mut Context<Self>) -> Self::Result {
+        self.store.reorder(&msg.ids);
+        self.notify_waiters();
+        MessageResult(self.store.queued().cloned().collect())
+    }
+}
+
+/// Queue failed jobs again
+#[derive(Message)]
+#[rtype(result = "Result<Vec<Job>, YodelError>")]
+enum RetryJobs {
+    One(Uuid),
+    /// All failed jobs, optionally skipping the ones that can't succeed
+    Failed {
+        skip_permanent: bool,
+    },
+}
+
+impl Handler<RetryJobs> for JobServer {
+    type Result = Result<Vec<Job>, YodelError>;
+
+    fn handle(&mut self, msg: RetryJobs, ctx: &mut Context<Self>) -> Self::Result {
+        let retried = match msg {
+            RetryJobs::One(id) => vec![self.store.retry(id)?],
+            RetryJobs::Failed { skip_permanent } => {
+                let mut ids: Vec<(DateTime<Utc>, Uuid)> = self
+                    .store
+                    .failed()
+                    .filter(|job| !(skip_permanent && job.failed_permanently()))
+                    .map(|job| (job.started_on, job.id))
+                    .collect();
+                // keep the original submission order in the queue
+                ids.sort();
+
+                let mut retried = Vec::new();
+                for (_, id) in ids {
+                    match self.store.retry(id) {
+                        Ok(job) => retried.push(job),
+                        Err(YodelError::TooManyJobs) => {
+                            warn!("the queue is full, not all failed jobs were retried");
+                            break;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                retried
+            }
+        };
+
+        info!("retrying {} jobs", retried.len());
+        self.dispatch(ctx);
+        self.broadcast_changes();
+        self.notify_waiters();
+        Ok(retried)
+    }
+}
+
+/// Stop a running job, keeping what was downloaded so far
+#[derive(Message)]
+#[rtype(result = "Result<Job, YodelError>")]
+struct PauseJob {
+    id: Uuid,
+}
+
+impl Handler<PauseJob> for JobServer {
+    type Result = Result<Job, YodelError>;
+
+    fn handle(&mut self, msg: PauseJob, ctx: &mut Context<Self>) -> Self::Result {
+        let job = self
+            .store
+            .get(&msg.id)
+            .ok_or_else(|| YodelError::NotFound(format!("job {}", msg.id)))?;
+        if !job.in_progress() {
+            return Err(YodelError::BadRequest(format!("{} is not running", job)));
+        }
+
+        // killing youtube-dl leaves the .part file, the result of the download is ignored
+        // the job isn't resumed before youtube-dl exited, see `DownloadEnded`
+        if let Some(download) = self.downloads.get(&msg.id) {
+            download.cancel();
+        }
+        self.stop_duration_timer(&msg.id, ctx);
+
+        let job = self
+            .store
+            .pause(msg.id)
+            .ok_or_else(|| YodelError::NotFound(format!("job {}", msg.id)))?;
+        info!("paused {}", job);
+
+        self.dispatch(ctx);
+        self.broadcast_changes();
+        self.notify_waiters();
+        Ok(job)
+    }
+}
+
+/// Queue a paused job again, in front of the other queued jobs
+#[derive(Message)]
+#[rtype(result = "Result<Job, YodelError>")]
+struct ResumeJob {
+    id: Uuid,
+}
+
+impl Handler<ResumeJob> for JobServer {
+    type Result = Result<Job, YodelError>;
+
+    fn handle(&mut self, msg: ResumeJob, ctx: &mut Context<Self>) -> Self::Result {
+        let job = self.store.resume(msg.id)?;
+        info!("resuming {}", job);
+
+        self.dispatch(ctx);
+        self.broadcast_changes();
+        self.notify_waiters();
+        Ok(job)
+    }
+}
+
+/// Queue a completed job again with some of its options changed
+#[derive(Message)]
+#[rtype(result = "Result<Job, YodelError>")]
+struct RetryWith {
+    id: Uuid,
+    changes: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Handler<RetryWith> for JobServer {
+    type Result = Result<Job, YodelError>;
+
+    fn handle(&mut self, msg: RetryWith, ctx: &mut Context<Self>) -> Self::Result {
+        let job = self
+            .store
+            .get(&msg.id)
+            .ok_or_else(|| YodelError::NotFound(format!("job {}", msg.id)))?;
+        if !job.has_failed() && !job.has_succeeded() {
+            return Err(YodelError::BadRequest(format!(
+                "{} did not finish or fail",
+                job
+            )));
+        }
+
+        // the job's options include the configured defaults, only the changes were requested
+        check_allowed_names(
+            msg.changes.keys().map(|name| snake_case(name)),
+            CONFIG.allowed_options.as_deref(),
+        )?;
+        let options = job.options.merge(msg.changes)?;
+        options.validate()?;
+        let job = self.store.retry_with(msg.id, options.with_defaults())?;
+        info!("retrying {} with other options", job);
+
+        self.dispatch(ctx);
+        self.broadcast_changes();
+        self.notify_waiters();
+        Ok(job)
+    }
+}
+
+/// Which job to cancel
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum CancelTarget {
+    Id(Uuid),
+    /// The url and location the job was submitted with
+    /// Jobs with different options can share those, cancelling them this way is a conflict
+    Submitted {
+        url: String,
+        location: Option<String>,
+    },
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<Job, YodelError>")]
+struct CancelJob {
+    target: CancelTarget,
+}
+
+impl Handler<CancelJob> for JobServer {
+    type Result = Result<Job, YodelError>;
+
+    fn handle(&mut self, msg: CancelJob, ctx: &mut Context<Self>) -> Self::Result {
+        let id = match msg.target {
+            CancelTarget::Id(id) => {
+                let job = self
+                    .store
+                    .get(&id)
+                    .ok_or_else(|| YodelError::NotFound(format!("job {}", id)))?;
+                if job.is_completed() {
+                    return Err(YodelError::BadRequest(format!(
+                        "{} is already completed",
+                        job
+                    )));
+                }
+                id
+            }
+            CancelTarget::Submitted { url, location } => {
+                let location = location.or_else(|| CONFIG.default_location.clone());
+                let matching = self.store.find_all(|job| {
+                    !job.is_completed()
+                        && job.url == url
+                        && location.as_deref() == Some(job.location.name())
+                });
+                match matching.as_slice() {
+                    [] => return Err(YodelError::NotFound(format!("job for {}", url))),
+                    [job] => job.id(),
+                    _ => {
+                        return Err(YodelError::Conflict(format!(
+                            "{} jobs for {} were submitted with different options, cancel one by its id",
+                            matching.len(),
+                            url
+                        )))
+                    }
+                }
+            }
+        };
+
+        if let Some(download) = self.downloads.get(&id) {
+            download.cancel();
+        }
+        self.stop_duration_timer(&id, ctx);
+
+        let job = self
+            .store
+            .cancel(id)
+            .ok_or_else(|| YodelError::NotFound(format!("job {}", id)))?;
+        info!("cancelled {}", job);
+
+        self.dispatch(ctx);
+        self.broadcast_changes();
+        self.notify_waiters();
+        Ok(job)
+    }
+}
+
+#[derive(Message)]
+#[rtype(usize)]
+pub(crate) struct Connect {
+    pub(crate) addr: Recipient<Broadcast>,
+}
+
+/// Send a connected client all jobs again as a snapshot
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct Refresh {
+    pub(crate) addr: Recipient<Broadcast>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Disconnect {
+    pub id: usize,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<JobList, std::io::Error>")]
+pub enum JobQuery {
+    Pending,
+    Completed,
+    /// Both the pending and completed jobs, taken from the same snapshot
+    All,
+    /// Like `All`, with the time of the snapshot's latest change for long-polling clients
+    Changes,
+}
+
+/// The jobs returned for a `JobQuery`
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum JobList {
+    Jobs(Vec<Job>),
+    All {
+        pending: Vec<Job>,
+        completed: Vec<Job>,
+    },
+    Changes {
+        pending: Vec<Job>,
+        completed: Vec<Job>,
+        #[serde(rename = "lastModified")]
+        last_modified: DateTime<Utc>,
+    },
+}
+
+impl JobList {
+    /// Only keep the jobs with the given tag
+    fn with_tag(self, tag: &str) -> JobList {
+        let tagged = |jobs: Vec<Job>| -> Vec<Job> {
+            jobs.into_iter().filter(|job| job.has_tag(tag)).collect()
+        };
+        match self {
+            JobList::Jobs(jobs) => JobList::Jobs(tagged(jobs)),
+            JobList::All { pending, completed } => JobList::All {
+                pending: tagged(pending),
+                completed: tagged(completed),
+            },
+            JobList::Changes {
+                pending,
+                completed,
+                last_modified,
+            } => JobList::Changes {
+                pending: tagged(pending),
+                completed: tagged(completed),
+                last_modified,
+            },
+        }
+    }
+}
+
+/// Number of jobs with each tag
+#[derive(Message)]
+#[rtype(result = "BTreeMap<String, usize>")]
+struct GetTags;
+
+impl Handler<GetTags> for JobServer {
+    type Result = MessageResult<GetTags>;
+
+    fn handle(&mut self, _: GetTags, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.store.tags())
+    }
+}
+
+impl Handler<AtCapacity> for JobServer {
+    type Result = bool;
+
+    fn handle(&mut self, _: AtCapacity, _: &mut Context<Self>) -> Self::Result {
+        self.store.at_capacity()
+    }
+}
+
+impl Handler<admin::Drain> for JobServer {
+    type Result = MessageResult<admin::Drain>;
+
+    fn handle(&mut self, _: admin::Drain, _: &mut Context<Self>) -> Self::Result {
+        if !self.draining {
+            info!("draining, no new jobs will be started");
+            self.draining = true;
+        }
+        MessageResult(self.drain_status())
+    }
+}
+
+impl Handler<admin::GetDrainStatus> for JobServer {
+    type Result = MessageResult<admin::GetDrainStatus>;
+
+    fn handle(&mut self, _: admin::GetDrainStatus, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.drain_status())
+    }
+}
+
+impl Handler<admin::UpdateDownloader> for JobServer {
+    type Result = Result<oneshot::Receiver<admin::UpdateResult>, YodelError>;
+
+    fn handle(&mut self, _: admin::UpdateDownloader, ctx: &mut Context<Self>) -> Self::Result {
+        self.update_downloader(ctx)
+    }
+}
+
+impl Handler<admin::UpdateFinished> for JobServer {
+    type Result = ();
+
+    fn handle(&mut self, _: admin::UpdateFinished, ctx: &mut Context<Self>) -> Self::Result {
+        self.updating = false;
+        // start the jobs that were queued during the update
+        self.dispatch(ctx);
+    }
+}
+
+impl Handler<admin::GetProcesses> for JobServer {
+    type Result = MessageResult<admin::GetProcesses>;
+
+    fn handle(&mut self, _: admin::GetProcesses, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(
+            self.downloads
+                .iter()
+                .filter_map(|(id, download)| {
+                    Some(admin::Process {
+                        id: *id,
+                        pid: download.pid()?,
+                    })
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Handler<GetStats> for JobServer {
+    type Result = MessageResult<GetStats>;
+
+    fn handle(&mut self, _: GetStats, _: &mut Context<Self>) -> Self::Result {
+        // only configured locations are reported, to keep the number of metrics bounded
+        let locations = CONFIG
+            .location_names()
+            .map(|name| {
+                let counts = self.job_counts(|job| job.location().name() == name);
+                (name.clone(), counts)
+            })
+            .collect();
+
+        MessageResult(Stats {
+            jobs: self.job_counts(|_| true),
+            locations,
+            total_bytes_downloaded: self.counters.total_bytes_downloaded,
+            sessions: self.sessions.len(),
+            title_cache_hits: self.titles.hits,
+            title_cache_misses: self.titles.misses,
+            progress: self.overall_progress(),
+        })
+    }
+}
+
+/// Wait for the jobs to change after `since`
+#[derive(Message)]
+#[rtype(result = "PollResponse")]
+struct PollJobs {
+    since: DateTime<Utc>,
+}
+
+enum PollResponse {
+    /// The jobs already changed after `since`
+    Changed,
+    /// Resolves on the next change
+    Wait(oneshot::Receiver<()>),
+}
+
+impl Handler<PollJobs> for JobServer {
+    type Result = MessageResult<PollJobs>;
+
+    fn handle(&mut self, poll: PollJobs, _: &mut Context<Self>) -> Self::Result {
+        if self.store.last_modified() > poll.since {
+            return MessageResult(PollResponse::Changed);
+        }
+
+        // forget the clients that stopped waiting
+        self.waiters.retain(|waiter| !waiter.is_canceled());
+
+        let (sender, receiver) = oneshot::channel();
+        self.waiters.push(sender);
+        MessageResult(PollResponse::Wait(receiver))
+    }
+}
+
+/// User facing messages
+/// Serialized as `{"type": "pendingJobs", "data": [...]}`
+#[derive(Debug, Message, Serialize, Clone)]
+#[rtype(result = "()")]
+#[serde(tag = "type", content = "data", rename_all = "camelCase")]
+pub(crate) enum JobResponse {
+    // Start(Job),
+    /// the download progressed, the job contains the latest progress
+    Progress(Job),
+    Finished(Job),
+    /// job finished, but unsuccessfully
+    Failed {
+        job: Job,
+        reason: String,
+    },
+    /// something the user should know about, that didn't make the job fail
+    Warning {
+        job: Job,
+        message: String,
+    },
+    /// a job was added or changed, sent instead of the whole job lists
+    JobUpdated(Job),
+    /// all jobs, sent to clients when they connect or ask for it
+    Snapshot {
+        pending: Vec<Job>,
+        completed: Vec<Job>,
+    },
+}
+
+/// A `JobResponse` sent to all websocket clients
+/// `seq` increases with every broadcast, so clients can discard messages that arrive out of order
+#[derive(Debug, Message, Serialize, Clone)]
+#[rtype(result = "()")]
+pub(crate) struct Broadcast {
+    seq: u64,
+    #[serde(flatten)]
+    response: JobResponse,
+}
+
+impl Broadcast {
+    pub(crate) fn response(&self) -> &JobResponse {
+        &self.response
+    }
+}
+
+impl Actor for JobServer {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        // start the jobs that were still queued before a restart
+        self.dispatch(ctx);
+
+        if CONFIG.auto_update {
+            let interval = Duration::from_secs(CONFIG.auto_update_interval * 60 * 60);
+            ctx.run_interval(interval, |act, ctx| {
+                // a skipped update is tried again the next interval
+                if let Err(e) = act.update_downloader(ctx) {
+                    warn!("skipping the automatic update: {}", e);
+                }
+            });
+        }
+    }
+}
+
+impl Handler<Refresh> for JobServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Refresh, _: &mut Context<Self>) {
+        let _ = msg.addr.do_send(self.snapshot());
+    }
+}
+
+impl Handler<Connect> for JobServer {
+    type Result = usize;
+
+    fn handle(&mut self, msg: Connect, _ctx: &mut Context<Self>) -> Self::Result {
+        let session_id = self.rng.gen::<usize>();
+        let _ = msg.addr.do_send(self.snapshot());
+        self.sessions.insert(session_id, msg.addr);
+
+        info!("new connection!");
+
+        session_id
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "PrunedSessions")]
+struct PruneSessions;
+
+#[derive(Serialize, Debug)]
+struct PrunedSessions {
+    pruned: usize,
+    sessions: usize,
+}
+
+impl Handler<PruneSessions> for JobServer {
+    type Result = MessageResult<PruneSessions>;
+
+    fn handle(&mut self, _: PruneSessions, _: &mut Context<Self>) -> Self::Result {
+        let pruned = self.prune_sessions();
+        info!("pruned {} disconnected sessions", pruned);
+        MessageResult(PrunedSessions {
+            pruned,
+            sessions: self.sessions.len(),
+        })
+    }
+}
+
+impl Handler<Disconnect> for JobServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
+        info!("connection lost");
+        self.sessions.remove(&msg.id);
+    }
+}
+
+impl Handler<JobQuery> for JobServer {
+    type Result = Result<JobList, std::io::Error>;
+
+    fn handle(&mut self, query: JobQuery, _: &mut Context<Self>) -> Self::Result {
+        match query {
+            JobQuery::Pending => Ok(JobList::Jobs(self.store.pending())),
+            JobQuery::Completed => Ok(JobList::Jobs(self.store.finished())),
+            JobQuery::All => Ok(JobList::All {
+                pending: self.store.pending(),
+                completed: self.store.finished(),
+            }),
+            JobQuery::Changes => Ok(JobList::Changes {
+                pending: self.store.pending(),
+                completed: self.store.finished(),
+                last_modified: self.store.last_modified(),
+            }),
+        }
+    }
+}
+
+impl Handler<JobResponse> for JobServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: JobResponse, ctx: &mut Context<Self>) {
+        info!("Request received: {:?}", msg);
+        let id = match &msg {
+            JobResponse::Finished(job) | JobResponse::Failed { job, .. } => {
+                // the job was restarted since, the download and timers belong to the new attempt
+                if !self.store.is_current_attempt(&job.id, job.attempt) {
+                    debug!("ignoring the outcome of an earlier attempt of {}", job);
+                    return;
+                }
+                Some(job.id)
+            }
+            _ => None,
+        };
+        if let Some(id) = id {
+            self.forget_progress(&id, ctx);
+            self.stop_duration_timer(&id, ctx);
+            // a cancelled job already failed, youtube-dl being killed doesn't change that
+            if !self.store.get(&id).is_some_and(Job::in_progress) {
+                return;
+            }
+        }
+
+        match msg {
+            JobResponse::Finished(job) => {
+                if let Some(job) = self.store.set_finished(job.id(), job.output) {
+                    self.counters.total_bytes_downloaded += job.output.size_bytes.unwrap_or(0);
+                    if self.counter_writer.send(self.counters.clone()).is_err() {
+                        error!("unable to save the counters, their writer stopped");
+                    }
+                    notify_completed(&job);
+                    for copy in &job.output.copies {
+                        if let Some(error) = &copy.error {
+                            let message = format!("Copying to {} failed: {}", copy.location, error);
+                            warn!("{}: {}", job, message);
+                            self.broadcast(&JobResponse::Warning {
+                                job: job.clone(),
+                                message,
+                            });
+                        }
+                    }
+                    if let Some(downloads) = job.output.truncated_after {
+                        let message = format!(
+                            "The playlist was cut off after {} downloaded videos",
+                            downloads
+                        );
+                        warn!("{}: {}", job, message);
+                        self.broadcast(&JobResponse::Warning {
+                            job: job.clone(),
+                            message,
+                        });
+                    }
+                    self.broadcast(&JobResponse::Finished(job));
+                }
+                self.dispatch(ctx);
+                self.broadcast_changes();
+                self.notify_waiters();
+            }
+            JobResponse::Failed { job, reason } => {
+                if let Some(job) = self.store.set_failed(job.id(), reason.clone()) {
+                    notify_completed(&job);
+                    self.broadcast(&JobResponse::Failed { job, reason });
+                }
+                self.dispatch(ctx);
+                self.broadcast_changes();
+                self.notify_waiters();
+            }
+            _ => (),
+        }
+    }
+}
+
+#[post("/jobs")]
+async fn create_job(
+    request: Json<JobRequest>,
     job_server: web::Data<actix::Addr<JobServer>>,
 ) -> Result<actix_web::HttpResponse, YodelError> {
-    let res = job_server.send(request.into_inner()).await?;
+    let request = request.into_inner();
+    let job = web::block(move || prepare_job(request)).await?;
+    let res = job_server.send(AddJob { job }).await?;
 
     match res {
-        Ok(job) => Ok(HttpResponse::Accepted().json(job)),
+        Ok(Submission::Created(job)) => Ok(HttpResponse::Accepted().json(Cased(job))),
+        Ok(Submission::Bumped(job)) => Ok(HttpResponse::Ok().json(Cased(job))),
         Err(e) => Err(e),
     }
 }
 
+#[derive(Deserialize, Debug)]
+struct QuickAddQuery {
+    url: String,
+    location: Option<String>,
+}
+
+/// Add a job from a link, eg: a bookmarklet, and respond with a page saying how it went
+/// Changing state with a GET request is unusual, so this has to be enabled with `quick_add`
+#[get("/quick-add")]
+async fn quick_add(
+    query: web::Query<QuickAddQuery>,
+    job_server: web::Data<actix::Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    if !CONFIG.quick_add {
+        return Err(YodelError::NotFound("quick-add is disabled".to_string()));
+    }
+
+    let query = query.into_inner();
+    let request = JobRequest {
+        url: query.url,
+        location: query.location,
+        locations: None,
+        tags: None,
+        options: JobOptions::default(),
+    };
+
+    let submission = match web::block(move || prepare_job(request)).await {
+        Ok(job) => job_server.send(AddJob { job }).await?,
+        Err(e) => Err(e.into()),
+    };
+    let (status, message) = match submission {
+        Ok(Submission::Created(job)) => (StatusCode::ACCEPTED, format!("Added {}", job)),
+        Ok(Submission::Bumped(job)) => (StatusCode::OK, format!("Moved {} up the queue", job)),
+        Err(e) => (e.error_response().status(), e.to_string()),
+    };
+
+    Ok(HttpResponse::build(status)
+        .content_type("text/html; charset=utf-8")
+        .body(format!(
+            "<!DOCTYPE html><title>yodel</title><p>{}</p>",
+            escape_html(&message)
+        )))
+}
+
+/// Escape text for use in an html page
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[derive(Deserialize, Debug)]
+struct JobListQuery {
+    /// Only list the jobs with this tag
+    tag: Option<String>,
+}
+
 #[get("/jobs")]
-async fn pending_jobs(job_server: web::Data<actix::Addr<JobServer>>) -> impl Responder {
-    let jobs: Vec<Job> = job_server
-        .send(JobQuery::Pending)
-        .await
-        .expect("Actix message error")
-        .expect("This should never happen");
-    HttpResponse::Ok().json(jobs)
+async fn pending_jobs(
+    req: HttpRequest,
+    query: web::Query<JobListQuery>,
+    job_server: web::Data<actix::Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    conditional_job_list(&req, &job_server, JobQuery::Pending, query.tag.as_deref()).await
 }
 
 #[get("/completed-jobs")]
-async fn completed_jobs(job_server: web::Data<actix::Addr<JobServer>>) -> impl Responder {
-    let jobs: Vec<Job> = job_server
-        .send(JobQuery::Completed)
-        .await
-        .expect("Actix message error")
+async fn completed_jobs(
+    req: HttpRequest,
+    query: web::Query<JobListQuery>,
+    job_server: web::Data<actix::Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    conditional_job_list(&req, &job_server, JobQuery::Completed, query.tag.as_deref()).await
+}
+
+/// The tags in use, with the number of jobs that have them
+#[get("/tags")]
+async fn tags(job_server: web::Data<actix::Addr<JobServer>>) -> Result<HttpResponse, YodelError> {
+    let tags = job_server.send(GetTags).await?;
+    Ok(HttpResponse::Ok().json(tags))
+}
+
+/// Respond with the queried jobs, or with 304 Not Modified
+/// if the client's ETag shows it already has the latest version
+/// The ETag is a hash of the response, so every change is noticed, including the progress
+async fn conditional_job_list(
+    req: &HttpRequest,
+    job_server: &Addr<JobServer>,
+    query: JobQuery,
+    tag: Option<&str>,
+) -> Result<HttpResponse, YodelError> {
+    let mut jobs: JobList = job_server
+        .send(query)
+        .await?
+        .expect("This should never happen");
+    if let Some(tag) = tag {
+        jobs = jobs.with_tag(&tag.trim().to_lowercase());
+    }
+
+    let body = serde_json::to_vec(&Cased(jobs)).map_err(|e| {
+        error!("unable to serialize the jobs: {}", e);
+        YodelError::InternalServerError
+    })?;
+    let etag = format!("\"{:x}\"", Sha256::digest(&body));
+
+    if etag_matches(req, &etag) {
+        return Ok(HttpResponse::NotModified()
+            .header(header::ETAG, etag)
+            .finish());
+    }
+
+    Ok(HttpResponse::Ok()
+        .header(header::ETAG, etag)
+        .content_type("application/json")
+        .body(body))
+}
+
+/// Returns true if the request's If-None-Match header contains `etag`
+fn etag_matches(req: &HttpRequest, etag: &str) -> bool {
+    req.headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value.split(',').any(|tag| {
+                let tag = tag.trim();
+                tag == "*" || tag.trim_start_matches("W/") == etag
+            })
+        })
+}
+
+#[get("/jobs/all")]
+async fn all_jobs(
+    job_server: web::Data<actix::Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    let jobs: JobList = job_server
+        .send(JobQuery::All)
+        .await?
+        .expect("This should never happen");
+
+    Ok(HttpResponse::Ok().json(Cased(jobs)))
+}
+
+/// Set the order of the queue, takes the job ids in the desired order
+/// Returns the new queue
+#[put("/queue/order")]
+async fn reorder_queue(
+    ids: Json<Vec<Uuid>>,
+    job_server: web::Data<actix::Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    let queue = job_server
+        .send(ReorderQueue {
+            ids: ids.into_inner(),
+        })
+        .await?;
+
+    Ok(HttpResponse::Ok().json(Cased(queue)))
+}
+
+#[derive(Deserialize, Debug)]
+struct PollQuery {
+    since: Option<DateTime<Utc>>,
+}
+
+/// Long-polling fallback for clients that can't use the websocket
+/// Waits until the jobs change after `since`, or the poll times out, and returns all jobs
+/// Without `since` all jobs are returned immediately
+#[get("/jobs/poll")]
+async fn poll_jobs(
+    query: web::Query<PollQuery>,
+    job_server: web::Data<actix::Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    if let Some(since) = query.since {
+        if let PollResponse::Wait(changed) = job_server.send(PollJobs { since }).await? {
+            // a timeout means nothing changed, the current jobs are returned either way
+            let _ = actix_web::rt::time::timeout(POLL_TIMEOUT, changed).await;
+        }
+    }
+
+    let jobs: JobList = job_server
+        .send(JobQuery::Changes)
+        .await?
         .expect("This should never happen");
-    HttpResponse::Ok().json(jobs)
+
+    Ok(HttpResponse::Ok().json(Cased(jobs)))
+}
+
+#[get("/jobs/{id}")]
+async fn get_job(
+    id: web::Path<Uuid>,
+    job_server: web::Data<actix::Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    let job = job_server
+        .send(GetJob {
+            id: id.into_inner(),
+        })
+        .await??;
+
+    Ok(HttpResponse::Ok().json(Cased(job)))
+}
+
+#[derive(Deserialize, Debug)]
+struct FileQuery {
+    /// Stream a running download as it's written
+    #[serde(default)]
+    follow: bool,
+}
+
+/// Download the file of a finished job, supports range requests
+/// With `follow`, the file of a running job is streamed until the job finishes
+#[get("/jobs/{id}/file")]
+async fn job_file(
+    id: web::Path<Uuid>,
+    query: web::Query<FileQuery>,
+    job_server: web::Data<actix::Addr<JobServer>>,
+) -> Result<Either<NamedFile, HttpResponse>, YodelError> {
+    let job = job_server
+        .send(GetJob {
+            id: id.into_inner(),
+        })
+        .await??;
+
+    if query.follow && job.in_progress() {
+        let destination = match &job.destination {
+            Some(destination) => destination.clone(),
+            None => return Err(YodelError::NotFound(format!("file of {}", job))),
+        };
+        confine(
+            destination.parent().unwrap_or(&destination),
+            job.download_dir(),
+        )?;
+
+        let follow = FollowedFile {
+            id: job.id,
+            destination,
+            offset: 0,
+            job_server: job_server.get_ref().clone(),
+        };
+        let stream = futures::stream::unfold(Some(follow), |follow| async move {
+            let mut follow = follow?;
+            match follow.next_chunk().await {
+                Ok(Some(chunk)) => Some((Ok(chunk), Some(follow))),
+                Ok(None) => None,
+                // an error ends the response early, the client sees an incomplete download
+                Err(e) => Some((Err(e), None)),
+            }
+        });
+
+        return Ok(Either::B(
+            HttpResponse::Ok()
+                .content_type("application/octet-stream")
+                .streaming(Box::pin(stream)),
+        ));
+    }
+
+    let path = match (&job.status, &job.output.path) {
+        (JobStatus::Finished, Some(path)) if path.exists() => path,
+        _ => return Err(YodelError::NotFound(format!("file of {}", job))),
+    };
+    confine(path, job.location.path())?;
+
+    Ok(Either::A(NamedFile::open(path)?))
+}
+
+/// A download that's read while youtube-dl writes it
+struct FollowedFile {
+    id: Uuid,
+    destination: PathBuf,
+    offset: u64,
+    job_server: Addr<JobServer>,
+}
+
+impl FollowedFile {
+    /// How long to wait for youtube-dl to write more
+    const POLL_INTERVAL: Duration = Duration::from_millis(500);
+    /// Maximum size of a chunk of the response
+    const CHUNK_SIZE: u64 = 64 * 1024;
+
+    /// The next part of the file, or `None` once the job finished and everything was read
+    async fn next_chunk(&mut self) -> Result<Option<web::Bytes>, YodelError> {
+        loop {
+            // the status is checked before reading, so nothing written before the job
+            // finished can be missed
+            let job = self.job_server.send(GetJob { id: self.id }).await??;
+
+            // youtube-dl writes to a .part file and renames it when the download is done
+            let mut partial = self.destination.clone().into_os_string();
+            partial.push(".part");
+            let partial = PathBuf::from(partial);
+            let path = if partial.exists() {
+                partial
+            } else {
+                self.destination.clone()
+            };
+
+            let offset = self.offset;
+            let chunk = web::block(move || read_chunk(&path, offset, Self::CHUNK_SIZE)).await?;
+            if !chunk.is_empty() {
+                self.offset += chunk.len() as u64;
+                return Ok(Some(web::Bytes::from(chunk)));
+            }
+
+            match &job.status {
+                JobStatus::InProgress => actix_web::rt::time::delay_for(Self::POLL_INTERVAL).await,
+                // merged or converted downloads end up in another file than the one followed
+                JobStatus::Finished | JobStatus::AwaitingReview
+                    if job.output.path.as_ref() == Some(&self.destination) =>
+                {
+                    return Ok(None)
+                }
+                _ => {
+                    return Err(YodelError::NotFound(format!(
+                        "the download of {} did not complete",
+                        job
+                    )))
+                }
+            }
+        }
+    }
+}
+
+/// Read up to `size` bytes of a file, starting at `offset`
+/// A missing file reads as empty, youtube-dl might not have created it yet
+fn read_chunk(path: &Path, offset: u64, size: u64) -> std::io::Result<Vec<u8>> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut reader = BufReader::new(file);
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut chunk = Vec::new();
+    reader.take(size).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+/// The thumbnail image of a finished job, see `JobOptions::write_thumbnail`
+#[get("/jobs/{id}/thumbnail")]
+async fn job_thumbnail(
+    id: web::Path<Uuid>,
+    job_server: web::Data<actix::Addr<JobServer>>,
+) -> Result<NamedFile, YodelError> {
+    let job = job_server
+        .send(GetJob {
+            id: id.into_inner(),
+        })
+        .await??;
+
+    let path = match (&job.status, &job.output.thumbnail) {
+        (JobStatus::Finished, Some(path)) if path.exists() => path,
+        _ => return Err(YodelError::NotFound(format!("thumbnail of {}", job))),
+    };
+    confine(path, job.location.path())?;
+
+    Ok(NamedFile::open(path)?)
+}
+
+#[derive(Deserialize, Debug)]
+struct ApproveRequest {
+    /// Defaults to the job's own location
+    location: Option<String>,
+}
+
+/// Move a staged download to its location
+#[post("/jobs/{id}/approve")]
+async fn approve_job(
+    id: web::Path<Uuid>,
+    request: Json<ApproveRequest>,
+    job_server: web::Data<actix::Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    let id = id.into_inner();
+    let location = match &request.location {
+        Some(name) => Some(
+            Location::lookup(name)
+                .ok_or_else(|| YodelError::BadRequest("Invalid Location".to_string()))?,
+        ),
+        None => None,
+    };
+
+    let job = job_server.send(PrepareReview { id, location }).await??;
+    let location = job.location.clone();
+    let output = web::block(move || -> Result<JobOutput, YodelError> {
+        let mut output = job
+            .output
+            .move_to(job.download_dir(), job.location.path())?;
+        output.copies = output.copy_to_locations(job.location.path(), &job.extra_locations);
+        Ok(output)
+    })
+    .await?;
+
+    let job = job_server
+        .send(ReviewJob {
+            id,
+            review: Review::Approved {
+                location,
+                output: Box::new(output),
+            },
+        })
+        .await??;
+
+    Ok(HttpResponse::Ok().json(Cased(job)))
+}
+
+/// Delete a staged download
+#[post("/jobs/{id}/reject")]
+async fn reject_job(
+    id: web::Path<Uuid>,
+    job_server: web::Data<actix::Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    let id = id.into_inner();
+    let job = job_server
+        .send(PrepareReview { id, location: None })
+        .await??;
+    web::block(move || job.output.remove(job.download_dir())).await?;
+
+    let job = job_server
+        .send(ReviewJob {
+            id,
+            review: Review::Rejected,
+        })
+        .await??;
+
+    Ok(HttpResponse::Ok().json(Cased(job)))
+}
+
+/// Remove websocket sessions that disconnected without notifying the server
+#[post("/admin/sessions/prune")]
+async fn prune_sessions(
+    req: HttpRequest,
+    job_server: web::Data<actix::Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    admin::authorize(&req)?;
+
+    let pruned = job_server.send(PruneSessions).await?;
+    Ok(HttpResponse::Ok().json(Cased(pruned)))
+}
+
+/// Pause a running job
+#[post("/jobs/{id}/pause")]
+async fn pause_job(
+    id: web::Path<Uuid>,
+    job_server: web::Data<actix::Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    let job = job_server
+        .send(PauseJob {
+            id: id.into_inner(),
+        })
+        .await??;
+
+    Ok(HttpResponse::Ok().json(Cased(job)))
+}
+
+/// Continue a paused job
+#[post("/jobs/{id}/resume")]
+async fn resume_job(
+    id: web::Path<Uuid>,
+    job_server: web::Data<actix::Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    let job = job_server
+        .send(ResumeJob {
+            id: id.into_inner(),
+        })
+        .await??;
+
+    Ok(HttpResponse::Ok().json(Cased(job)))
+}
+
+/// Cancel a queued, running or paused job by its id
+#[delete("/jobs/{id}")]
+async fn cancel_job(
+    id: web::Path<Uuid>,
+    job_server: web::Data<actix::Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    let target = CancelTarget::Id(id.into_inner());
+    let job = job_server.send(CancelJob { target }).await??;
+
+    Ok(HttpResponse::Ok().json(Cased(job)))
+}
+
+/// Cancel a queued or running job by the url and location it was submitted with
+/// Fails with a conflict when several jobs match, they're cancelled by their id instead
+#[delete("/jobs")]
+async fn cancel_submitted_job(
+    target: Json<CancelTarget>,
+    job_server: web::Data<actix::Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    let target = target.into_inner();
+    let job = job_server.send(CancelJob { target }).await??;
+
+    Ok(HttpResponse::Ok().json(Cased(job)))
+}
+
+/// Queue a failed job again
+#[post("/jobs/{id}/retry")]
+async fn retry_job(
+    id: web::Path<Uuid>,
+    job_server: web::Data<actix::Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    let mut retried = job_server.send(RetryJobs::One(id.into_inner())).await??;
+
+    Ok(HttpResponse::Ok().json(Cased(retried.pop())))
+}
+
+/// Queue a finished or failed job again, the body has the options to change
+#[post("/jobs/{id}/retry-with")]
+async fn retry_job_with(
+    id: web::Path<Uuid>,
+    changes: Json<serde_json::Map<String, serde_json::Value>>,
+    job_server: web::Data<actix::Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    let job = job_server
+        .send(RetryWith {
+            id: id.into_inner(),
+            changes: changes.into_inner(),
+        })
+        .await??;
+
+    Ok(HttpResponse::Ok().json(Cased(job)))
+}
+
+#[derive(Deserialize, Debug)]
+struct RetryFailedQuery {
+    /// Don't retry jobs that failed for a reason retrying won't fix, eg: an unsupported url
+    #[serde(default)]
+    skip_permanent: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct RetriedJobs {
+    retried: usize,
+}
+
+/// Queue all failed jobs again, returns how many were queued
+#[post("/jobs/retry-failed")]
+async fn retry_failed_jobs(
+    query: web::Query<RetryFailedQuery>,
+    job_server: web::Data<actix::Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    let retried = job_server
+        .send(RetryJobs::Failed {
+            skip_permanent: query.skip_permanent,
+        })
+        .await??;
+
+    Ok(HttpResponse::Ok().json(Cased(RetriedJobs {
+        retried: retried.len(),
+    })))
+}
+
+#[derive(Serialize, Debug)]
+struct JobCommand {
+    downloader: PathBuf,
+    directory: PathBuf,
+    args: Vec<String>,
+    /// Names of the configured environment variables, their values are secret
+    environment: Vec<String>,
+}
+
+/// The youtube-dl command line of a job, with the secrets redacted
+#[get("/jobs/{id}/command")]
+async fn job_command(
+    req: HttpRequest,
+    id: web::Path<Uuid>,
+    job_server: web::Data<actix::Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    admin::authorize(&req)?;
+
+    let job = job_server
+        .send(GetJob {
+            id: id.into_inner(),
+        })
+        .await??;
+
+    Ok(HttpResponse::Ok().json(Cased(JobCommand {
+        downloader: CONFIG.downloader.clone(),
+        directory: job.download_dir().to_path_buf(),
+        args: redact(build_args(&job)),
+        environment: CONFIG.environment.keys().cloned().collect(),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A server that holds off the downloads, with the titles of `urls` cached so none are looked up
+    fn idle_server(urls: &[&str]) -> JobServer {
+        let mut server = JobServer::new();
+        server.updating = true;
+        for url in urls {
+            server.titles.insert(url.to_string(), "title".to_string());
+        }
+        server
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn build_args_ends_with_the_url() {
+        let job = Job::for_test("https://example.com/watch?v=1");
+        let args = build_args(&job);
+
+        assert_eq!(args.last().unwrap(), "https://example.com/watch?v=1");
+        assert!(args.windows(2).any(|pair| pair == ["-o", "%(title)s.mp4"]));
+        assert!(args
+            .windows(2)
+            .any(|pair| pair == ["--match-filter", "!is_live"]));
+    }
+
+    #[test]
+    fn build_args_adds_the_requested_options() {
+        let mut job = Job::for_test("https://example.com/watch?v=1");
+        job.options = JobOptions {
+            retries: Some(3),
+            remux: Some("mkv".to_string()),
+            allow_live: true,
+            ..Default::default()
+        };
+        let args = build_args(&job);
+
+        assert!(args.windows(2).any(|pair| pair == ["--retries", "3"]));
+        assert!(args
+            .windows(2)
+            .any(|pair| pair == ["--fragment-retries", "3"]));
+        assert!(args.windows(2).any(|pair| pair == ["--remux-video", "mkv"]));
+        assert!(!args.iter().any(|arg| arg == "--match-filter"));
+    }
+
+    #[test]
+    fn build_args_skips_subtitles_for_audio() {
+        let mut job = Job::for_test("https://example.com/watch?v=1");
+        job.options = JobOptions {
+            audio_only: true,
+            subtitle_mode: SubtitleMode::Both,
+            subtitle_langs: Some("en,nl".to_string()),
+            ..Default::default()
+        };
+        let args = build_args(&job);
+
+        assert!(args.iter().any(|arg| arg == "--extract-audio"));
+        for flag in &[
+            "--all-subs",
+            "--write-sub",
+            "--write-subs",
+            "--sub-lang",
+            "--sub-langs",
+            "--embed-subs",
+        ] {
+            assert!(!args.iter().any(|arg| arg == flag), "{} was added", flag);
+        }
+    }
+
+    #[test]
+    fn retrying_with_allowed_options_ignores_the_defaults() {
+        let allowed = ["retries".to_string(), "remux".to_string()];
+        let options = JobOptions {
+            restrict_filenames: Some(true),
+            prefer_free_formats: Some(true),
+            embed_chapters: Some(true),
+            ..Default::default()
+        };
+        let mut changes = serde_json::Map::new();
+        changes.insert("retries".to_string(), serde_json::json!(3));
+
+        // the merged options contain the defaults, which weren't requested
+        let merged = options.merge(changes.clone()).unwrap();
+        assert!(check_allowed_names(merged.requested(), Some(&allowed[..])).is_err());
+        assert!(check_allowed_names(
+            changes.keys().map(|name| snake_case(name)),
+            Some(&allowed[..])
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn check_allowed_names_rejects_options_that_are_not_listed() {
+        let allowed = ["retries".to_string()];
+        let names = || vec!["retries".to_string(), "audio_only".to_string()];
+
+        assert!(matches!(
+            check_allowed_names(names(), Some(&allowed[..])),
+            Err(YodelError::BadRequest(_))
+        ));
+        assert!(check_allowed_names(names(), None).is_ok());
+        assert!(check_allowed_names(Vec::new(), Some(&[][..])).is_ok());
+    }
+
+    #[test]
+    fn redact_hides_credentials() {
+        let redacted = redact(args(&[
+            "--username",
+            "bart",
+            "--password",
+            "hunter2",
+            "--cookies=/home/bart/cookies.txt",
+            "https://example.com/watch?v=1",
+        ]));
+
+        assert_eq!(
+            redacted,
+            args(&[
+                "--username",
+                "bart",
+                "--password",
+                "<redacted>",
+                "--cookies=<redacted>",
+                "https://example.com/watch?v=1",
+            ])
+        );
+    }
+
+    #[test]
+    fn redact_keeps_other_arguments() {
+        let command = args(&["--newline", "-o", "%(title)s.mp4", "--proxy"]);
+        assert_eq!(redact(command.clone()), command);
+    }
+
+    #[test]
+    fn parse_counts_the_downloaded_items_of_a_truncated_playlist() {
+        let stdout = r#"[download] Downloading video 1 of 5
+[download] Destination: first.f137.mp4
+[download] Destination: first.f140.m4a
+[ffmpeg] Merging formats into "first.mp4"
+[download] Downloading video 2 of 5
+[download] second.mp4 has already been downloaded and merged
+[download] Downloading video 3 of 5
+[download] Destination: third.en.vtt
+[download] Destination: third.webm
+[ExtractAudio] Destination: third.mp3
+[download] Maximum number of downloaded files reached"#;
+        let output = JobOutput::parse(stdout, Path::new("/downloads"));
+
+        assert_eq!(output.truncated_after, Some(3));
+        assert_eq!(output.path, Some(PathBuf::from("/downloads/third.mp3")));
+    }
+
+    /// A path in the temporary directory that doesn't exist yet
+    fn temporary_path() -> PathBuf {
+        std::env::temp_dir().join(format!("yodel-test-{}", Uuid::new_v4()))
+    }
+
+    #[test]
+    fn check_directories_fails_on_missing_directories_unless_they_are_created() {
+        let path = temporary_path();
+        let mut job = Job::for_test("https://example.com/watch?v=1");
+        job.location = Location::new("test", &path);
+
+        assert!(matches!(
+            job.check_directories(false),
+            Err(YodelError::BadRequest(_))
+        ));
+        assert!(!path.exists());
+
+        job.check_directories(true).unwrap();
+        assert!(path.is_dir());
+        assert!(job.check_directories(false).is_ok());
+
+        fs::remove_dir(&path).unwrap();
+    }
+
+    #[test]
+    fn check_directories_is_a_server_error_when_creating_fails() {
+        // a file is in the way of the directory
+        let file = temporary_path();
+        fs::write(&file, "").unwrap();
+        let mut job = Job::for_test("https://example.com/watch?v=1");
+        job.location = Location::new("test", &file.join("videos"));
+
+        assert!(matches!(
+            job.check_directories(true),
+            Err(YodelError::InternalServerError)
+        ));
+
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn find_config_file_only_finds_files_in_the_directory() {
+        let directory = temporary_path();
+        fs::create_dir(&directory).unwrap();
+        fs::write(directory.join("audio.conf"), "-x").unwrap();
+        fs::create_dir(directory.join("nested")).unwrap();
+
+        assert_eq!(
+            find_config_file("audio.conf", &directory),
+            Some(directory.canonicalize().unwrap().join("audio.conf"))
+        );
+        for name in &[
+            "",
+            "missing.conf",
+            "nested",
+            "../audio.conf",
+            "..",
+            "nested/../audio.conf",
+            "/etc/passwd",
+            "nested\\audio.conf",
+        ] {
+            assert_eq!(find_config_file(name, &directory), None, "{:?}", name);
+        }
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn output_template_keeps_the_requested_extension() {
+        let options = JobOptions {
+            filename: Some("clip.webm".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(options.output_template(), "clip.webm");
+
+        let options = JobOptions {
+            filename: Some("clip".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(options.output_template(), "clip.mp4");
+    }
+
+    #[test]
+    fn output_template_replaces_the_extension_youtube_dl_picks() {
+        let options = JobOptions {
+            filename: Some("100%.mp4".to_string()),
+            audio_only: true,
+            ..Default::default()
+        };
+        assert_eq!(options.output_template(), "100%%.%(ext)s");
+
+        let options = JobOptions {
+            filename: Some("clip.mp4".to_string()),
+            remux: Some("mkv".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(options.output_template(), "clip.%(ext)s");
+    }
+
+    #[test]
+    fn cancelling_a_url_submitted_with_different_options_is_a_conflict() {
+        let url = "https://example.com/watch?v=1";
+        actix_web::rt::System::new("test").block_on(async move {
+            let server = idle_server(&[url]).start();
+            let video = Job::for_test(url);
+            let mut audio = Job::for_test(url);
+            audio.options.audio_only = true;
+            for job in [video, audio] {
+                server.send(AddJob { job }).await.unwrap().unwrap();
+            }
+
+            let target = CancelTarget::Submitted {
+                url: url.to_string(),
+                location: Some("test".to_string()),
+            };
+            let cancelled = server.send(CancelJob { target }).await.unwrap();
+
+            assert!(matches!(cancelled, Err(YodelError::Conflict(_))));
+        });
+    }
+
+    #[test]
+    fn the_same_url_submitted_twice_at_once_is_added_once() {
+        let url = "https://example.com/watch?v=1";
+        actix_web::rt::System::new("test").block_on(async move {
+            let server = idle_server(&[url]).start();
+            let (first, second) = futures::join!(
+                server.send(AddJob {
+                    job: Job::for_test(url)
+                }),
+                server.send(AddJob {
+                    job: Job::for_test(url)
+                }),
+            );
+            let results = [first.unwrap(), second.unwrap()];
+
+            assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+            assert_eq!(
+                results
+                    .iter()
+                    .filter(|result| matches!(result, Err(YodelError::Conflict(_))))
+                    .count(),
+                1
+            );
+            let pending = server.send(JobQuery::Pending).await.unwrap().unwrap();
+            assert!(matches!(pending, JobList::Jobs(jobs) if jobs.len() == 1));
+        });
+    }
 }
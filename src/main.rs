@@ -4,14 +4,36 @@ extern crate log;
 use actix::prelude::*;
 use actix_cors::Cors;
 use actix_files::Files;
+use actix_web::dev::Service;
+use actix_web::http::header::{HeaderValue, CACHE_CONTROL};
 use actix_web::middleware::Logger;
-use actix_web::{web, App, HttpServer};
+use actix_web::{web, App, HttpResponse, HttpServer};
+use futures::future::{self, Either};
 
+use crate::config::CONFIG;
+
+mod admin;
+mod auth;
+mod casing;
 mod config;
+mod desktop;
 mod errors;
+mod formats;
+mod health;
 mod jobs;
+mod repository;
+mod self_test;
+mod stats;
+mod store;
+mod telegram;
+mod title_cache;
+mod version;
+mod webhook;
 mod websocket;
 
+/// The server log, also written to stdout
+const LOG_FILE: &str = "output.log";
+
 #[actix_web::main]
 async fn main() -> Result<(), terminator::Terminator> {
     init().await?;
@@ -24,22 +46,111 @@ async fn init() -> std::io::Result<()> {
         eprintln!("Something went wrong while setting up the logger: {}", e);
         std::process::exit(1);
     }
+    version::check();
+
+    if self_test::requested() {
+        match self_test::run() {
+            Ok(()) => {
+                info!("self-test succeeded");
+                std::process::exit(0);
+            }
+            Err(e) => {
+                error!("self-test failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
     let job_server = jobs::JobServer::new().start();
     HttpServer::new(move || {
-        App::new()
+        let app = App::new()
             .data(job_server.clone())
-            .wrap(Logger::default())
+            // the default format logs the query string, which can hold the websocket's token
+            .wrap(Logger::new(
+                "%a \"%U\" %s %b \"%{Referer}i\" \"%{User-Agent}i\" %T",
+            ))
+            .wrap_fn(|req, srv| {
+                let cache_control = cache_control(req.path());
+                let res = srv.call(req);
+                async move {
+                    let mut res = res.await?;
+                    if let Some(value) = cache_control {
+                        if !res.headers().contains_key(CACHE_CONTROL) {
+                            res.headers_mut()
+                                .insert(CACHE_CONTROL, HeaderValue::from_static(value));
+                        }
+                    }
+                    Ok(res)
+                }
+            })
             .wrap(Cors::permissive().supports_credentials())
             .service(
                 web::scope("/api")
+                    .wrap_fn(|req, srv| match auth::check(&req) {
+                        Ok(()) => Either::Left(srv.call(req)),
+                        Err(e) => Either::Right(future::err(e.into())),
+                    })
+                    .service(admin::logs)
+                    .service(admin::drain)
+                    .service(admin::drain_status)
+                    .service(admin::processes)
+                    .service(admin::update_downloader)
                     .service(config::locations)
+                    .service(formats::formats)
+                    .service(formats::estimate)
+                    .service(health::healthz)
+                    .service(health::readyz)
+                    .service(version::version)
+                    .service(stats::stats)
                     .service(jobs::pending_jobs)
+                    .service(jobs::all_jobs)
+                    .service(jobs::poll_jobs)
+                    .service(jobs::get_job)
+                    .service(jobs::job_file)
+                    .service(jobs::job_thumbnail)
+                    .service(jobs::job_command)
+                    .service(jobs::approve_job)
+                    .service(jobs::reject_job)
                     .service(jobs::completed_jobs)
-                    .service(jobs::create_job),
+                    .service(jobs::create_job)
+                    .service(jobs::quick_add)
+                    .service(jobs::retry_failed_jobs)
+                    .service(jobs::retry_job)
+                    .service(jobs::retry_job_with)
+                    .service(jobs::pause_job)
+                    .service(jobs::resume_job)
+                    .service(jobs::reorder_queue)
+                    .service(jobs::cancel_job)
+                    .service(jobs::cancel_submitted_job)
+                    .service(jobs::tags)
+                    .service(websocket::job_route)
+                    .service(jobs::prune_sessions),
             )
-            .service(web::resource("/ws").to(websocket::route))
-            .service(mount_frontend())
+            .service(
+                web::resource("/ws")
+                    .wrap_fn(|req, srv| match auth::check(&req) {
+                        Ok(()) => Either::Left(srv.call(req)),
+                        Err(e) => Either::Right(future::err(e.into())),
+                    })
+                    .to(websocket::route),
+            )
+            .service(
+                web::resource("/metrics")
+                    .wrap_fn(|req, srv| match auth::check(&req) {
+                        Ok(()) => Either::Left(srv.call(req)),
+                        Err(e) => Either::Right(future::err(e.into())),
+                    })
+                    .route(web::get().to(stats::metrics)),
+            );
+
+        if CONFIG.api_only {
+            app.route("/", web::get().to(api_index))
+        } else {
+            app.service(mount_frontend())
+        }
     })
+    .max_connections(CONFIG.max_connections)
+    .max_connection_rate(CONFIG.max_connection_rate)
     .bind("127.0.0.1:8080")?
     .run()
     .await
@@ -64,7 +175,7 @@ fn init_logger() -> std::io::Result<()> {
         .level_for("hyper", log::LevelFilter::Info)
         // Output to stdout, files, and other Dispatch configurations
         .chain(std::io::stdout())
-        .chain(fern::log_file("output.log")?)
+        .chain(fern::log_file(LOG_FILE)?)
         // Apply globally
         .apply()
         // This only fails if a logger was already setup, this is a developer error
@@ -72,12 +183,43 @@ fn init_logger() -> std::io::Result<()> {
     Ok(())
 }
 
-#[cfg(target_os = "freebsd")]
+/// Served at the root in API only mode, instead of the frontend
+async fn api_index() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({
+        "name": "yodel",
+        "message": "This is the yodel API, the frontend is disabled",
+        "version": "/api/version",
+    }))
+}
+
+/// Cache header for the frontend files
+/// The build puts files with a hash in their name in /static, so they never change,
+/// the index refers to them and is revalidated to pick up a new build
+fn cache_control(path: &str) -> Option<&'static str> {
+    if path.starts_with("/static/") {
+        Some("public, max-age=31536000, immutable")
+    } else if path == "/" || path.ends_with(".html") {
+        Some("no-cache")
+    } else {
+        None
+    }
+}
+
+/// Files supports range requests, ETags and Last-Modified headers out of the box
 fn mount_frontend() -> Files {
-    Files::new("/", "frontend").index_file("index.html")
+    match &CONFIG.frontend_dir {
+        Some(frontend_dir) => Files::new("/", frontend_dir),
+        None => Files::new("/", default_frontend_dir()),
+    }
+    .index_file("index.html")
+}
+
+#[cfg(target_os = "freebsd")]
+fn default_frontend_dir() -> &'static str {
+    "frontend"
 }
 
 #[cfg(not(target_os = "freebsd"))]
-fn mount_frontend() -> Files {
-    Files::new("/", "frontend/build").index_file("index.html")
+fn default_frontend_dir() -> &'static str {
+    "frontend/build"
 }
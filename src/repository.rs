@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rusqlite::{params, Connection};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::config::CONFIG;
+use crate::jobs::Job;
+
+/// How long the writer collects changed jobs before saving them together
+const WRITE_DELAY: Duration = Duration::from_millis(500);
+
+/// Where the jobs are kept across restarts, inside of the data directory
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Storage {
+    /// A jobs.json file
+    #[default]
+    File,
+    /// A jobs.sqlite database
+    Sqlite,
+}
+
+/// Persists the jobs so they survive a restart
+pub(crate) trait JobRepository: fmt::Debug + Send {
+    /// All stored jobs, oldest first
+    fn load(&mut self) -> Result<Vec<Job>, String>;
+
+    /// Insert the job, or replace the stored job with the same id
+    fn save(&mut self, job: &Job) -> Result<(), String>;
+
+    /// Save several jobs at once
+    fn save_all(&mut self, jobs: &[Job]) -> Result<(), String> {
+        jobs.iter().try_for_each(|job| self.save(job))
+    }
+}
+
+/// Saves the jobs it's sent on a background thread, so the job server doesn't wait for the disk
+/// Jobs that change again shortly after are saved once, with their latest state
+#[derive(Debug)]
+pub(crate) struct Writer {
+    sender: Option<mpsc::Sender<Job>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Writer {
+    pub(crate) fn spawn(mut repository: Box<dyn JobRepository>) -> Writer {
+        let (sender, receiver) = mpsc::channel::<Job>();
+
+        let thread = thread::spawn(move || {
+            while let Ok(job) = receiver.recv() {
+                let mut jobs = HashMap::new();
+                jobs.insert(job.id(), job);
+
+                let deadline = Instant::now() + WRITE_DELAY;
+                let mut disconnected = false;
+                while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+                    match receiver.recv_timeout(timeout) {
+                        Ok(job) => {
+                            jobs.insert(job.id(), job);
+                        }
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Err(RecvTimeoutError::Disconnected) => {
+                            disconnected = true;
+                            break;
+                        }
+                    }
+                }
+
+                let jobs: Vec<Job> = jobs.into_values().collect();
+                if let Err(e) = repository.save_all(&jobs) {
+                    error!("unable to save {} jobs: {}", jobs.len(), e);
+                }
+                if disconnected {
+                    break;
+                }
+            }
+        });
+
+        Writer {
+            sender: Some(sender),
+            thread: Some(thread),
+        }
+    }
+
+    /// Save the job in the background
+    pub(crate) fn save(&self, job: Job) {
+        let sent = self
+            .sender
+            .as_ref()
+            .is_some_and(|sender| sender.send(job).is_ok());
+        if !sent {
+            error!("unable to save a job, the writer stopped");
+        }
+    }
+}
+
+impl Drop for Writer {
+    /// Wait for the jobs that weren't saved yet
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("the job writer panicked");
+            }
+        }
+    }
+}
+
+/// Open the configured repository
+/// Returns `None` when no data directory is configured or the repository can't be opened
+pub(crate) fn open() -> Option<Box<dyn JobRepository>> {
+    let data_dir = CONFIG.data_dir.as_ref()?;
+
+    let repository: Result<Box<dyn JobRepository>, String> = match CONFIG.storage {
+        Storage::File => Ok(Box::new(FileRepository::new(data_dir.join("jobs.json")))),
+        Storage::Sqlite => SqliteRepository::open(&data_dir.join("jobs.sqlite"))
+            .map(|repository| Box::new(repository) as Box<dyn JobRepository>),
+    };
+
+    match repository {
+        Ok(repository) => Some(repository),
+        Err(e) => {
+            error!(
+                "unable to open the job repository, jobs won't be saved: {}",
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Keeps all jobs in a single json file, which is rewritten on every change
+#[derive(Debug)]
+pub(crate) struct FileRepository {
+    file: PathBuf,
+    jobs: HashMap<Uuid, Job>,
+}
+
+impl FileRepository {
+    fn new(file: PathBuf) -> FileRepository {
+        FileRepository {
+            file,
+            jobs: HashMap::new(),
+        }
+    }
+}
+
+impl JobRepository for FileRepository {
+    fn load(&mut self) -> Result<Vec<Job>, String> {
+        if !self.file.exists() {
+            return Ok(Vec::new());
+        }
+
+        let contents = std::fs::read_to_string(&self.file).map_err(|e| e.to_string())?;
+        self.jobs = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
+
+        let mut jobs: Vec<Job> = self.jobs.values().cloned().collect();
+        jobs.sort_by_key(Job::started_on);
+        Ok(jobs)
+    }
+
+    fn save(&mut self, job: &Job) -> Result<(), String> {
+        self.save_all(std::slice::from_ref(job))
+    }
+
+    /// The file is rewritten once for all of the jobs
+    fn save_all(&mut self, jobs: &[Job]) -> Result<(), String> {
+        for job in jobs {
+            self.jobs.insert(job.id(), job.clone());
+        }
+
+        // write to a temporary file first so a crash can't leave a half written file
+        let temporary = self.file.with_extension("json.tmp");
+        let contents = serde_json::to_vec(&self.jobs).map_err(|e| e.to_string())?;
+        std::fs::write(&temporary, contents).map_err(|e| e.to_string())?;
+        std::fs::rename(&temporary, &self.file).map_err(|e| e.to_string())
+    }
+}
+
+/// Keeps the jobs in a SQLite table, with the status and start date as queryable columns
+pub(crate) struct SqliteRepository {
+    connection: Connection,
+}
+
+impl fmt::Debug for SqliteRepository {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SqliteRepository").finish()
+    }
+}
+
+impl SqliteRepository {
+    fn open(file: &Path) -> Result<SqliteRepository, String> {
+        let connection = Connection::open(file).map_err(|e| e.to_string())?;
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS jobs (
+                    id TEXT PRIMARY KEY,
+                    status TEXT NOT NULL,
+                    started_on TEXT NOT NULL,
+                    job TEXT NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS jobs_status ON jobs (status);
+                CREATE INDEX IF NOT EXISTS jobs_started_on ON jobs (started_on);",
+            )
+            .map_err(|e| e.to_string())?;
+
+        Ok(SqliteRepository { connection })
+    }
+}
+
+impl JobRepository for SqliteRepository {
+    fn load(&mut self) -> Result<Vec<Job>, String> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT job FROM jobs ORDER BY started_on")
+            .map_err(|e| e.to_string())?;
+        let rows = statement
+            .query_map(params![], |row| row.get::<_, String>(0))
+            .map_err(|e| e.to_string())?;
+
+        let mut jobs = Vec::new();
+        for row in rows {
+            let row = row.map_err(|e| e.to_string())?;
+            jobs.push(serde_json::from_str(&row).map_err(|e| e.to_string())?);
+        }
+
+        Ok(jobs)
+    }
+
+    fn save(&mut self, job: &Job) -> Result<(), String> {
+        let serialized = serde_json::to_string(job).map_err(|e| e.to_string())?;
+        self.connection
+            .execute(
+                "INSERT OR REPLACE INTO jobs (id, status, started_on, job) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    job.id().to_string(),
+                    job.status().name(),
+                    job.started_on().to_rfc3339(),
+                    serialized
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+
+    /// The jobs are saved in a single transaction
+    fn save_all(&mut self, jobs: &[Job]) -> Result<(), String> {
+        self.connection
+            .execute_batch("BEGIN")
+            .map_err(|e| e.to_string())?;
+
+        match jobs.iter().try_for_each(|job| self.save(job)) {
+            Ok(()) => self
+                .connection
+                .execute_batch("COMMIT")
+                .map_err(|e| e.to_string()),
+            Err(e) => {
+                let _ = self.connection.execute_batch("ROLLBACK");
+                Err(e)
+            }
+        }
+    }
+}
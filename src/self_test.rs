@@ -0,0 +1,64 @@
+use std::path::Path;
+
+use crate::config::CONFIG;
+
+/// youtube-dl's own test video, it's only a few seconds long
+const TEST_URL: &str = "https://www.youtube.com/watch?v=BaW_jenozKc";
+
+/// Returns true if the self-test was requested with `--self-test` or `YODEL_SELFTEST=1`
+pub(crate) fn requested() -> bool {
+    std::env::args().any(|arg| arg == "--self-test")
+        || std::env::var("YODEL_SELFTEST").is_ok_and(|value| value == "1")
+}
+
+/// Download the test video to a temporary directory, which is removed afterwards
+/// This checks the downloader, ffmpeg and writing to the filesystem in one go
+pub(crate) fn run() -> Result<(), String> {
+    let directory = std::env::temp_dir().join(format!("yodel-self-test-{}", std::process::id()));
+    std::fs::create_dir_all(&directory)
+        .map_err(|e| format!("unable to create {}: {}", directory.display(), e))?;
+
+    let res = download(&directory);
+
+    if let Err(e) = std::fs::remove_dir_all(&directory) {
+        warn!("unable to remove {}: {}", directory.display(), e);
+    }
+
+    res
+}
+
+fn download(directory: &Path) -> Result<(), String> {
+    info!(
+        "self-test: downloading {} to {}",
+        TEST_URL,
+        directory.display()
+    );
+    let output = CONFIG
+        .downloader_command()
+        .current_dir(directory)
+        .arg("--no-playlist")
+        .arg("-o")
+        .arg("self-test.%(ext)s")
+        .arg(TEST_URL)
+        .output()
+        .map_err(|e| format!("unable to run {}: {}", CONFIG.downloader.display(), e))?;
+
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).to_string());
+    }
+
+    let downloaded = std::fs::read_dir(directory)
+        .map_err(|e| e.to_string())?
+        .filter_map(Result::ok)
+        .any(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with("self-test.")
+        });
+    if !downloaded {
+        return Err("the downloader succeeded, but no file was written".to_string());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::mpsc;
+
+use actix::prelude::*;
+use actix_web::{get, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::config::CONFIG;
+use crate::errors::YodelError;
+use crate::jobs::JobServer;
+
+/// Counters that are kept across restarts when a data directory is configured
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct Counters {
+    pub(crate) total_bytes_downloaded: u64,
+}
+
+impl Counters {
+    fn file() -> Option<PathBuf> {
+        CONFIG
+            .data_dir
+            .as_ref()
+            .map(|data_dir| data_dir.join("counters.json"))
+    }
+
+    /// Load the saved counters, starts from zero when there are none
+    pub(crate) fn load() -> Counters {
+        let file = match Counters::file() {
+            Some(file) if file.exists() => file,
+            _ => return Counters::default(),
+        };
+
+        let counters = std::fs::read_to_string(&file)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| serde_json::from_str(&contents).map_err(|e| e.to_string()));
+
+        match counters {
+            Ok(counters) => counters,
+            Err(e) => {
+                error!("unable to load the counters from {:?}: {}", file, e);
+                Counters::default()
+            }
+        }
+    }
+
+    /// Start a thread that saves the counters it's sent, so the job server doesn't wait for the disk
+    /// Counters that are sent while it's saving are coalesced, only the latest ones are saved
+    pub(crate) fn writer() -> mpsc::Sender<Counters> {
+        let (sender, receiver) = mpsc::channel::<Counters>();
+        std::thread::spawn(move || {
+            while let Ok(mut counters) = receiver.recv() {
+                while let Ok(newer) = receiver.try_recv() {
+                    counters = newer;
+                }
+                counters.save();
+            }
+        });
+        sender
+    }
+
+    /// Save the counters, if a data directory is configured
+    fn save(&self) {
+        let file = match Counters::file() {
+            Some(file) => file,
+            None => return,
+        };
+
+        // write to a temporary file first so a crash can't leave a half written file
+        let temporary = file.with_extension("json.tmp");
+        let res = serde_json::to_vec(self)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| std::fs::write(&temporary, contents).map_err(|e| e.to_string()))
+            .and_then(|_| std::fs::rename(&temporary, &file).map_err(|e| e.to_string()));
+
+        if let Err(e) = res {
+            error!("unable to save the counters to {:?}: {}", file, e);
+        }
+    }
+}
+
+/// Number of jobs by status
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct JobCounts {
+    pub(crate) queued: usize,
+    pub(crate) in_progress: usize,
+    pub(crate) finished: usize,
+    pub(crate) failed: usize,
+}
+
+impl JobCounts {
+    fn by_status(&self) -> [(&'static str, usize); 4] {
+        [
+            ("queued", self.queued),
+            ("in_progress", self.in_progress),
+            ("finished", self.finished),
+            ("failed", self.failed),
+        ]
+    }
+}
+
+/// Combined progress of the running downloads, jobs that didn't report progress are left out
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OverallProgress {
+    /// Number of running jobs that reported progress
+    pub(crate) jobs: usize,
+    pub(crate) average_percent: f64,
+    /// Seconds until the last of these downloads finishes, they run at the same time
+    /// Queued jobs aren't included, none of the jobs might know their ETA
+    pub(crate) eta: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Stats {
+    #[serde(flatten)]
+    pub(crate) jobs: JobCounts,
+    /// Jobs of every configured location
+    pub(crate) locations: BTreeMap<String, JobCounts>,
+    pub(crate) total_bytes_downloaded: u64,
+    /// Number of connected websocket clients
+    pub(crate) sessions: usize,
+    /// Title lookups that were answered from the cache, see `TitleCache`
+    pub(crate) title_cache_hits: u64,
+    pub(crate) title_cache_misses: u64,
+    /// Not set when no running job reported progress
+    pub(crate) progress: Option<OverallProgress>,
+}
+
+impl Stats {
+    /// Format the stats in the Prometheus text exposition format
+    fn to_prometheus(&self) -> String {
+        let mut metrics = String::new();
+
+        let _ = writeln!(metrics, "# HELP yodel_jobs Number of jobs by status");
+        let _ = writeln!(metrics, "# TYPE yodel_jobs gauge");
+        for (status, count) in &self.jobs.by_status() {
+            let _ = writeln!(metrics, "yodel_jobs{{status=\"{}\"}} {}", status, count);
+        }
+
+        let _ = writeln!(
+            metrics,
+            "# HELP yodel_location_jobs Number of jobs by location and status"
+        );
+        let _ = writeln!(metrics, "# TYPE yodel_location_jobs gauge");
+        for (location, jobs) in &self.locations {
+            // label values are quoted, escape the characters that would end them early
+            let location = location.replace('\\', "\\\\").replace('"', "\\\"");
+            for (status, count) in &jobs.by_status() {
+                let _ = writeln!(
+                    metrics,
+                    "yodel_location_jobs{{location=\"{}\",status=\"{}\"}} {}",
+                    location, status, count
+                );
+            }
+        }
+
+        let _ = writeln!(
+            metrics,
+            "# HELP yodel_downloaded_bytes_total Size of all finished downloads"
+        );
+        let _ = writeln!(metrics, "# TYPE yodel_downloaded_bytes_total counter");
+        let _ = writeln!(
+            metrics,
+            "yodel_downloaded_bytes_total {}",
+            self.total_bytes_downloaded
+        );
+
+        let _ = writeln!(
+            metrics,
+            "# HELP yodel_sessions Number of connected websocket clients"
+        );
+        let _ = writeln!(metrics, "# TYPE yodel_sessions gauge");
+        let _ = writeln!(metrics, "yodel_sessions {}", self.sessions);
+
+        let _ = writeln!(
+            metrics,
+            "# HELP yodel_title_cache_lookups_total Title lookups by whether the title was cached"
+        );
+        let _ = writeln!(metrics, "# TYPE yodel_title_cache_lookups_total counter");
+        for (result, count) in &[
+            ("hit", self.title_cache_hits),
+            ("miss", self.title_cache_misses),
+        ] {
+            let _ = writeln!(
+                metrics,
+                "yodel_title_cache_lookups_total{{result=\"{}\"}} {}",
+                result, count
+            );
+        }
+
+        if let Some(progress) = &self.progress {
+            let _ = writeln!(
+                metrics,
+                "# HELP yodel_progress_percent Average progress of the running downloads"
+            );
+            let _ = writeln!(metrics, "# TYPE yodel_progress_percent gauge");
+            let _ = writeln!(
+                metrics,
+                "yodel_progress_percent {}",
+                progress.average_percent
+            );
+        }
+
+        metrics
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "Stats")]
+pub(crate) struct GetStats;
+
+#[get("/stats")]
+async fn stats(job_server: web::Data<Addr<JobServer>>) -> Result<HttpResponse, YodelError> {
+    let stats = job_server.send(GetStats).await?;
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+/// Prometheus metrics, they need the read token like the rest of the API
+pub(crate) async fn metrics(
+    job_server: web::Data<Addr<JobServer>>,
+) -> Result<HttpResponse, YodelError> {
+    // `stats` is the /stats endpoint
+    let current = job_server.send(GetStats).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(current.to_prometheus()))
+}
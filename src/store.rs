@@ -0,0 +1,611 @@
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::config::Location;
+use crate::errors::YodelError;
+use crate::jobs::{Job, JobKey, JobOptions, JobOutput, Progress};
+use crate::repository::{JobRepository, Writer};
+
+/// Keeps track of all jobs and their state transitions
+/// This holds no actix state so it can be used outside of an actor system
+#[derive(Debug)]
+pub(crate) struct JobStore {
+    jobs: HashMap<Uuid, Job>,
+    /// Job ids by identity, used to detect duplicate jobs
+    keys: HashMap<JobKey, Uuid>,
+    /// Ids of the queued jobs, in the order they should be started
+    queue: VecDeque<Uuid>,
+    job_limit: usize,
+    queue_limit: usize,
+    /// When a job was last added, removed or changed
+    last_modified: DateTime<Utc>,
+    /// Ids of the jobs that were added or changed since the last `take_changed`
+    changed: Vec<Uuid>,
+    /// Saves the jobs in the background, they're only kept in memory without one
+    writer: Option<Writer>,
+}
+
+impl JobStore {
+    pub(crate) fn new(job_limit: usize, queue_limit: usize) -> JobStore {
+        JobStore {
+            jobs: HashMap::new(),
+            keys: HashMap::new(),
+            queue: VecDeque::new(),
+            job_limit,
+            queue_limit,
+            last_modified: Utc::now(),
+            changed: Vec::new(),
+            writer: None,
+        }
+    }
+
+    /// Create a store with the jobs saved in the repository, further changes are saved there too
+    /// Jobs that were running are failed, they were interrupted by the restart
+    pub(crate) fn open(
+        job_limit: usize,
+        queue_limit: usize,
+        mut repository: Box<dyn JobRepository>,
+    ) -> JobStore {
+        let jobs = repository.load().unwrap_or_else(|e| {
+            error!("unable to load the saved jobs: {}", e);
+            Vec::new()
+        });
+
+        let mut store = JobStore::new(job_limit, queue_limit);
+        store.writer = Some(Writer::spawn(repository));
+
+        info!("restoring {} jobs", jobs.len());
+        for job in jobs {
+            let id = job.id();
+            let was_running = job.in_progress();
+            if job.is_queued() {
+                store.queue.push_back(id);
+            }
+            store.keys.insert(job.key(), id);
+            store.jobs.insert(id, job);
+
+            if was_running {
+                store.set_failed(id, "Interrupted by a restart".to_string());
+            }
+        }
+        // clients get the restored jobs when they connect
+        store.changed.clear();
+
+        store
+    }
+
+    /// Save the job in the repository, if there is one
+    fn persist(&self, job: &Job) {
+        if let Some(writer) = &self.writer {
+            writer.save(job.clone());
+        }
+    }
+
+    pub(crate) fn last_modified(&self) -> DateTime<Utc> {
+        self.last_modified
+    }
+
+    pub(crate) fn get(&self, id: &Uuid) -> Option<&Job> {
+        self.jobs.get(id)
+    }
+
+    /// Returns true if `attempt` is the job's latest attempt, see `Job::attempt`
+    pub(crate) fn is_current_attempt(&self, id: &Uuid, attempt: u32) -> bool {
+        self.jobs
+            .get(id)
+            .is_some_and(|job| job.attempt() == attempt)
+    }
+
+    /// Returns true if the maximum allowed number of jobs is running
+    pub(crate) fn at_capacity(&self) -> bool {
+        let running_jobs = self.jobs.values().filter(|&job| job.in_progress()).count();
+        running_jobs >= self.job_limit
+    }
+
+    /// Tries to add a job to the end of the queue
+    /// Fails if the job was already added or the queue is full
+    pub(crate) fn add(&mut self, job: Job) -> Result<(), YodelError> {
+        if self.queue.len() >= self.queue_limit {
+            return Err(YodelError::TooManyJobs);
+        }
+
+        // deterministic ids can be taken by a job that was retried with other options
+        if self.jobs.contains_key(&job.id()) {
+            return Err(YodelError::Conflict(job.to_string()));
+        }
+        // the key is checked and claimed in one step
+        match self.keys.entry(job.key()) {
+            Entry::Occupied(_) => return Err(YodelError::Conflict(job.to_string())),
+            Entry::Vacant(entry) => {
+                entry.insert(job.id());
+            }
+        }
+
+        self.persist(&job);
+        self.queue.push_back(job.id());
+        self.changed.push(job.id());
+        self.jobs.insert(job.id(), job);
+        self.last_modified = Utc::now();
+        Ok(())
+    }
+
+    /// Takes the first queued job for which `can_start` returns true and marks it as in progress
+    /// Returns `None` when at capacity or when none of the queued jobs can be started
+    pub(crate) fn start_next<F>(&mut self, can_start: F) -> Option<Job>
+    where
+        F: Fn(&Job) -> bool,
+    {
+        if self.at_capacity() {
+            return None;
+        }
+
+        let jobs = &self.jobs;
+        let position = self
+            .queue
+            .iter()
+            .position(|id| jobs.get(id).is_some_and(&can_start))?;
+        let id = self.queue.remove(position)?;
+        self.update(id, |job| job.set_in_progress())
+    }
+
+    /// The queued jobs, in the order they will be started
+    pub(crate) fn queued(&self) -> impl Iterator<Item = &Job> {
+        self.queue.iter().filter_map(move |id| self.jobs.get(id))
+    }
+
+    /// Move the queued jobs with the given ids to the front of the queue, in that order
+    /// Ids of jobs that aren't queued are ignored, the other queued jobs keep their relative order
+    pub(crate) fn reorder(&mut self, ids: &[Uuid]) {
+        let mut queue = VecDeque::with_capacity(self.queue.len());
+        for id in ids {
+            if let Some(position) = self.queue.iter().position(|queued| queued == id) {
+                queue.extend(self.queue.remove(position));
+            }
+        }
+
+        queue.extend(self.queue.drain(..));
+        self.queue = queue;
+        self.last_modified = Utc::now();
+    }
+
+    /// Apply `update` to the stored job
+    /// Returns the updated job, or `None` if the job doesn't exist
+    fn update<F>(&mut self, id: Uuid, update: F) -> Option<Job>
+    where
+        F: FnOnce(&mut Job),
+    {
+        let job = self.jobs.get_mut(&id)?;
+        update(job);
+        let job = job.clone();
+        self.persist(&job);
+        self.last_modified = Utc::now();
+        if !self.changed.contains(&id) {
+            self.changed.push(id);
+        }
+        Some(job)
+    }
+
+    /// The jobs that were added or changed since the last call, in the order they changed
+    /// Progress isn't a change, it's sent on its own
+    pub(crate) fn take_changed(&mut self) -> Vec<Job> {
+        let jobs = &self.jobs;
+        self.changed
+            .drain(..)
+            .filter_map(|id| jobs.get(&id).cloned())
+            .collect()
+    }
+
+    /// Returns the id of the queued job with the given identity
+    pub(crate) fn queued_duplicate(&self, key: &JobKey) -> Option<Uuid> {
+        let id = self.keys.get(key)?;
+        if self.queue.contains(id) {
+            Some(*id)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the jobs for which `predicate` returns true, in no particular order
+    pub(crate) fn find_all<F>(&self, predicate: F) -> Vec<&Job>
+    where
+        F: Fn(&Job) -> bool,
+    {
+        self.jobs.values().filter(|job| predicate(job)).collect()
+    }
+
+    /// Put a failed job back at the end of the queue
+    /// Fails if the job doesn't exist, didn't fail or the queue is full
+    pub(crate) fn retry(&mut self, id: Uuid) -> Result<Job, YodelError> {
+        let job = self
+            .jobs
+            .get(&id)
+            .ok_or_else(|| YodelError::NotFound(format!("job {}", id)))?;
+        if !job.has_failed() {
+            return Err(YodelError::BadRequest(format!("{} did not fail", job)));
+        }
+
+        if self.queue.len() >= self.queue_limit {
+            return Err(YodelError::TooManyJobs);
+        }
+
+        self.queue.push_back(id);
+        self.update(id, |job| job.retry())
+            .ok_or_else(|| YodelError::NotFound(format!("job {}", id)))
+    }
+
+    /// Put a finished or failed job back at the end of the queue with other options
+    /// Fails if the job doesn't exist, the queue is full or the options make it a duplicate
+    pub(crate) fn retry_with(&mut self, id: Uuid, options: JobOptions) -> Result<Job, YodelError> {
+        let mut job = self
+            .jobs
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| YodelError::NotFound(format!("job {}", id)))?;
+        let old_key = job.key();
+        job.retry_with(options);
+        if self.is_duplicate(id, &job.key()) {
+            return Err(YodelError::Conflict(job.to_string()));
+        }
+
+        if self.queue.len() >= self.queue_limit {
+            return Err(YodelError::TooManyJobs);
+        }
+
+        self.keys.remove(&old_key);
+        self.keys.insert(job.key(), id);
+        self.queue.push_back(id);
+        self.update(id, |stored| *stored = job)
+            .ok_or_else(|| YodelError::NotFound(format!("job {}", id)))
+    }
+
+    /// Mark a running job as paused, it's not queued until it's resumed
+    pub(crate) fn pause(&mut self, id: Uuid) -> Option<Job> {
+        self.update(id, |job| job.set_paused())
+    }
+
+    /// Put a paused job at the front of the queue
+    /// Fails if the job doesn't exist, isn't paused or the queue is full
+    pub(crate) fn resume(&mut self, id: Uuid) -> Result<Job, YodelError> {
+        let job = self
+            .jobs
+            .get(&id)
+            .ok_or_else(|| YodelError::NotFound(format!("job {}", id)))?;
+        if !job.is_paused() {
+            return Err(YodelError::BadRequest(format!("{} is not paused", job)));
+        }
+
+        if self.queue.len() >= self.queue_limit {
+            return Err(YodelError::TooManyJobs);
+        }
+
+        self.queue.push_front(id);
+        self.update(id, |job| job.resume())
+            .ok_or_else(|| YodelError::NotFound(format!("job {}", id)))
+    }
+
+    /// Cancel a queued, running or paused job, a queued job is removed from the queue
+    pub(crate) fn cancel(&mut self, id: Uuid) -> Option<Job> {
+        self.queue.retain(|queued| *queued != id);
+        self.update(id, |job| job.set_failed("Cancelled".to_string()))
+    }
+
+    /// Returns true if a job other than `id` has the given identity
+    pub(crate) fn is_duplicate(&self, id: Uuid, key: &JobKey) -> bool {
+        self.keys.get(key).is_some_and(|other| *other != id)
+    }
+
+    /// Fails if the job doesn't exist or isn't awaiting review
+    fn awaiting_review(&self, id: Uuid) -> Result<&Job, YodelError> {
+        let job = self
+            .jobs
+            .get(&id)
+            .ok_or_else(|| YodelError::NotFound(format!("job {}", id)))?;
+        if !job.awaiting_review() {
+            return Err(YodelError::BadRequest(format!(
+                "{} is not awaiting review",
+                job
+            )));
+        }
+        Ok(job)
+    }
+
+    /// Approve a job that was awaiting review, its files were moved to `location`
+    /// Fails if the job was approved or rejected in the meantime
+    pub(crate) fn approve(
+        &mut self,
+        id: Uuid,
+        location: Location,
+        output: JobOutput,
+    ) -> Result<Job, YodelError> {
+        let old_key = self.awaiting_review(id)?.key();
+        let job = self
+            .update(id, |job| job.approve(location, output))
+            .ok_or_else(|| YodelError::NotFound(format!("job {}", id)))?;
+        self.keys.remove(&old_key);
+        self.keys.insert(job.key(), id);
+        Ok(job)
+    }
+
+    /// Reject a job that was awaiting review, its files were removed
+    /// Fails if the job was approved or rejected in the meantime
+    pub(crate) fn reject(&mut self, id: Uuid) -> Result<Job, YodelError> {
+        self.awaiting_review(id)?;
+        self.update(id, |job| job.reject())
+            .ok_or_else(|| YodelError::NotFound(format!("job {}", id)))
+    }
+
+    pub(crate) fn set_finished(&mut self, id: Uuid, output: JobOutput) -> Option<Job> {
+        self.update(id, |job| job.set_finished(output))
+    }
+
+    pub(crate) fn set_failed(&mut self, id: Uuid, reason: String) -> Option<Job> {
+        self.update(id, |job| job.set_failed(reason))
+    }
+
+    /// Progress isn't saved, it changes too often and means nothing after a restart
+    pub(crate) fn set_progress(&mut self, id: Uuid, progress: Progress) -> Option<Job> {
+        let job = self.jobs.get_mut(&id)?;
+        job.set_progress(progress);
+        self.last_modified = Utc::now();
+        Some(job.clone())
+    }
+
+    /// Like the progress, the file being downloaded isn't saved
+    pub(crate) fn set_destination(&mut self, id: Uuid, destination: PathBuf) {
+        if let Some(job) = self.jobs.get_mut(&id) {
+            job.set_destination(destination);
+        }
+    }
+
+    pub(crate) fn set_title(&mut self, id: Uuid, title: String) -> Option<Job> {
+        self.update(id, |job| job.set_title(title))
+    }
+
+    /// Returns the number of jobs for which `predicate` returns true
+    pub(crate) fn count<F>(&self, predicate: F) -> usize
+    where
+        F: Fn(&Job) -> bool,
+    {
+        self.jobs.values().filter(|job| predicate(job)).count()
+    }
+
+    /// Returns the number of jobs with each tag
+    pub(crate) fn tags(&self) -> BTreeMap<String, usize> {
+        let mut tags = BTreeMap::new();
+        for tag in self.jobs.values().flat_map(|job| job.tags()) {
+            *tags.entry(tag.clone()).or_insert(0) += 1;
+        }
+        tags
+    }
+
+    /// Returns all jobs that are queued or running
+    pub(crate) fn pending(&self) -> Vec<Job> {
+        self.jobs
+            .values()
+            .filter(|job: &&Job| !job.is_completed())
+            .cloned()
+            .collect()
+    }
+
+    pub(crate) fn running(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.values().filter(|job| job.in_progress())
+    }
+
+    pub(crate) fn failed(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.values().filter(|job| job.has_failed())
+    }
+
+    pub(crate) fn finished(&self) -> Vec<Job> {
+        self.jobs
+            .values()
+            .filter(|job: &&Job| job.is_completed())
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://example.com/watch?v=1";
+
+    fn queued_ids(store: &JobStore) -> Vec<Uuid> {
+        store.queued().map(Job::id).collect()
+    }
+
+    /// Add a job and run it until it fails
+    fn failed_job(store: &mut JobStore, url: &str) -> Uuid {
+        let job = Job::for_test(url);
+        let id = job.id();
+        store.add(job).unwrap();
+        store.start_next(|_| true).unwrap();
+        store.set_failed(id, "Broken".to_string()).unwrap();
+        id
+    }
+
+    #[test]
+    fn add_queues_the_job() {
+        let mut store = JobStore::new(1, 10);
+        let job = Job::for_test(URL);
+        store.add(job.clone()).unwrap();
+
+        assert_eq!(queued_ids(&store), vec![job.id()]);
+        assert_eq!(store.take_changed().len(), 1);
+        assert!(store.take_changed().is_empty());
+    }
+
+    #[test]
+    fn add_rejects_a_job_with_the_same_key() {
+        let mut store = JobStore::new(1, 10);
+        store.add(Job::for_test(URL)).unwrap();
+
+        // another id, but the same url, location and options
+        let duplicate = store.add(Job::for_test(URL));
+        assert!(matches!(duplicate, Err(YodelError::Conflict(_))));
+        assert_eq!(store.count(|_| true), 1);
+    }
+
+    #[test]
+    fn add_rejects_a_job_with_the_same_id() {
+        let mut store = JobStore::new(1, 10);
+        let job = Job::for_test(URL);
+        store.add(job.clone()).unwrap();
+
+        assert!(matches!(store.add(job), Err(YodelError::Conflict(_))));
+    }
+
+    #[test]
+    fn add_rejects_jobs_when_the_queue_is_full() {
+        let mut store = JobStore::new(1, 1);
+        store.add(Job::for_test(URL)).unwrap();
+
+        let full = store.add(Job::for_test("https://example.com/watch?v=2"));
+        assert!(matches!(full, Err(YodelError::TooManyJobs)));
+    }
+
+    #[test]
+    fn start_next_respects_the_job_limit() {
+        let mut store = JobStore::new(1, 10);
+        store.add(Job::for_test(URL)).unwrap();
+        store
+            .add(Job::for_test("https://example.com/watch?v=2"))
+            .unwrap();
+
+        assert!(store.start_next(|_| true).unwrap().in_progress());
+        assert!(store.start_next(|_| true).is_none());
+        assert_eq!(queued_ids(&store).len(), 1);
+    }
+
+    #[test]
+    fn retry_queues_a_failed_job_again() {
+        let mut store = JobStore::new(1, 10);
+        let id = failed_job(&mut store, URL);
+
+        let retried = store.retry(id).unwrap();
+        assert!(retried.is_queued());
+        assert_eq!(queued_ids(&store), vec![id]);
+    }
+
+    #[test]
+    fn a_cancelled_attempt_is_not_current_once_the_job_is_retried() {
+        let mut store = JobStore::new(1, 10);
+        let job = Job::for_test(URL);
+        let id = job.id();
+        store.add(job).unwrap();
+        let cancelled = store.start_next(|_| true).unwrap();
+        store.cancel(id).unwrap();
+
+        store.retry(id).unwrap();
+        let retried = store.start_next(|_| true).unwrap();
+
+        // youtube-dl of the cancelled attempt fails after the retry started
+        assert!(!store.is_current_attempt(&id, cancelled.attempt()));
+        assert!(store.is_current_attempt(&id, retried.attempt()));
+        assert!(store.get(&id).unwrap().in_progress());
+    }
+
+    #[test]
+    fn a_paused_attempt_is_not_current_once_the_job_is_resumed() {
+        let mut store = JobStore::new(1, 10);
+        let job = Job::for_test(URL);
+        let id = job.id();
+        store.add(job).unwrap();
+        let paused = store.start_next(|_| true).unwrap();
+        store.pause(id).unwrap();
+
+        store.resume(id).unwrap();
+        let resumed = store.start_next(|_| true).unwrap();
+
+        assert!(!store.is_current_attempt(&id, paused.attempt()));
+        assert!(store.is_current_attempt(&id, resumed.attempt()));
+    }
+
+    #[test]
+    fn retry_rejects_jobs_that_did_not_fail() {
+        let mut store = JobStore::new(1, 10);
+        let job = Job::for_test(URL);
+        store.add(job.clone()).unwrap();
+
+        assert!(matches!(
+            store.retry(job.id()),
+            Err(YodelError::BadRequest(_))
+        ));
+        assert!(matches!(
+            store.retry(Uuid::new_v4()),
+            Err(YodelError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn reorder_moves_the_given_jobs_to_the_front() {
+        let mut store = JobStore::new(1, 10);
+        let ids: Vec<Uuid> = (0..3)
+            .map(|i| {
+                let job = Job::for_test(&format!("{}{}", URL, i));
+                store.add(job.clone()).unwrap();
+                job.id()
+            })
+            .collect();
+
+        // unknown ids are ignored, the jobs that aren't mentioned keep their order
+        store.reorder(&[ids[2], Uuid::new_v4(), ids[0]]);
+        assert_eq!(queued_ids(&store), vec![ids[2], ids[0], ids[1]]);
+    }
+
+    #[test]
+    fn retry_with_moves_the_job_to_its_new_key() {
+        let mut store = JobStore::new(1, 10);
+        let id = failed_job(&mut store, URL);
+        let options: JobOptions =
+            serde_json::from_value(serde_json::json!({ "audio_only": true })).unwrap();
+
+        store.retry_with(id, options.clone()).unwrap();
+
+        // the old key is free again
+        let other = Job::for_test(URL);
+        store.add(other.clone()).unwrap();
+        // and the new key is taken
+        assert!(matches!(
+            store.retry_with(other.id(), options),
+            Err(YodelError::Conflict(_))
+        ));
+    }
+
+    #[test]
+    fn a_reviewed_job_cant_be_reviewed_again() {
+        let mut store = JobStore::new(1, 10);
+        let mut job = Job::for_test(URL);
+        job.stage();
+        let id = job.id();
+        store.add(job.clone()).unwrap();
+        store.start_next(|_| true).unwrap();
+        store.set_finished(id, JobOutput::default()).unwrap();
+        assert!(store.get(&id).unwrap().awaiting_review());
+
+        store
+            .approve(id, job.location().clone(), JobOutput::default())
+            .unwrap();
+        assert!(matches!(
+            store.approve(id, job.location().clone(), JobOutput::default()),
+            Err(YodelError::BadRequest(_))
+        ));
+        assert!(matches!(store.reject(id), Err(YodelError::BadRequest(_))));
+        assert!(store.get(&id).unwrap().has_succeeded());
+    }
+
+    #[test]
+    fn queued_duplicate_finds_only_queued_jobs() {
+        let mut store = JobStore::new(1, 10);
+        let job = Job::for_test(URL);
+        store.add(job.clone()).unwrap();
+        assert_eq!(store.queued_duplicate(&job.key()), Some(job.id()));
+
+        store.start_next(|_| true).unwrap();
+        assert_eq!(store.queued_duplicate(&job.key()), None);
+    }
+}
@@ -0,0 +1,75 @@
+use actix_web::client::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::jobs::{Job, JobStatus};
+
+/// Telegram bot that's told when jobs complete
+#[derive(Debug, Deserialize)]
+pub(crate) struct Telegram {
+    bot_token: String,
+    chat_id: ChatId,
+}
+
+/// Numeric chat id, or the @username of a channel
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+enum ChatId {
+    Id(i64),
+    Username(String),
+}
+
+#[derive(Debug, Serialize)]
+struct SendMessage {
+    chat_id: ChatId,
+    text: String,
+    parse_mode: &'static str,
+}
+
+impl Telegram {
+    /// Send a message about the completed job, without waiting for Telegram
+    pub(crate) fn notify(&self, job: &Job) {
+        let url = format!("https://api.telegram.org/bot{}/sendMessage", self.bot_token);
+        let message = SendMessage {
+            chat_id: self.chat_id.clone(),
+            text: message(job),
+            parse_mode: "HTML",
+        };
+
+        actix::spawn(async move {
+            match Client::default().post(url).send_json(&message).await {
+                Ok(response) if response.status().is_success() => {
+                    debug!("sent telegram notification");
+                }
+                Ok(response) => {
+                    error!("telegram notification rejected: {}", response.status());
+                }
+                Err(e) => error!("unable to send telegram notification: {}", e),
+            }
+        });
+    }
+}
+
+fn message(job: &Job) -> String {
+    let status = match job.status() {
+        JobStatus::Finished => "✅ Finished".to_string(),
+        JobStatus::AwaitingReview => "👀 Awaiting review".to_string(),
+        JobStatus::Failed(reason) => format!("❌ Failed: {}", escape(reason.trim())),
+        JobStatus::InProgress => "⏳ In progress".to_string(),
+        JobStatus::Queued => "⏳ Queued".to_string(),
+        JobStatus::Paused => "⏸ Paused".to_string(),
+    };
+
+    format!(
+        "<b>{}</b>\n{}\nLocation: <i>{}</i>",
+        escape(&job.to_string()),
+        status,
+        escape(job.location().name())
+    )
+}
+
+/// Escape the text for Telegram's HTML parse mode
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
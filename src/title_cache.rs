@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Recently looked up video titles by url, so resubmitting a url doesn't run youtube-dl again
+/// The least recently used title is dropped when the cache is full
+#[derive(Debug)]
+pub(crate) struct TitleCache {
+    entries: HashMap<String, Entry>,
+    capacity: usize,
+    /// Titles can change, they're looked up again after this long
+    ttl: Duration,
+    /// Increases on every use, the entry with the lowest `last_used` is the least recently used
+    uses: u64,
+    pub(crate) hits: u64,
+    pub(crate) misses: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    title: String,
+    fetched: Instant,
+    last_used: u64,
+}
+
+impl TitleCache {
+    /// A capacity of 0 disables the cache
+    pub(crate) fn new(capacity: usize, ttl: Duration) -> TitleCache {
+        TitleCache {
+            entries: HashMap::new(),
+            capacity,
+            ttl,
+            uses: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub(crate) fn get(&mut self, url: &str) -> Option<String> {
+        self.uses += 1;
+        let ttl = self.ttl;
+        let uses = self.uses;
+
+        match self.entries.get_mut(url) {
+            Some(entry) if entry.fetched.elapsed() < ttl => {
+                entry.last_used = uses;
+                self.hits += 1;
+                Some(entry.title.clone())
+            }
+            Some(_) => {
+                self.entries.remove(url);
+                self.misses += 1;
+                None
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub(crate) fn insert(&mut self, url: String, title: String) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&url) {
+            let least_recently_used = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(url, _)| url.clone());
+            if let Some(url) = least_recently_used {
+                self.entries.remove(&url);
+            }
+        }
+
+        self.uses += 1;
+        self.entries.insert(
+            url,
+            Entry {
+                title,
+                fetched: Instant::now(),
+                last_used: self.uses,
+            },
+        );
+    }
+}
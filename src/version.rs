@@ -0,0 +1,87 @@
+use std::ffi::OsStr;
+use std::process::Command;
+use std::sync::RwLock;
+
+use actix_web::{get, HttpResponse, Responder};
+use serde::Serialize;
+
+use crate::config::CONFIG;
+
+lazy_static::lazy_static! {
+    static ref VERSIONS: RwLock<Versions> = RwLock::new(Versions::detect());
+}
+
+#[get("/version")]
+async fn version() -> impl Responder {
+    HttpResponse::Ok().json(&*VERSIONS.read().expect("versions lock poisoned"))
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Versions {
+    yodel: &'static str,
+    downloader: Option<String>,
+    ffmpeg: Option<String>,
+}
+
+impl Versions {
+    fn detect() -> Versions {
+        Versions {
+            yodel: env!("CARGO_PKG_VERSION"),
+            downloader: command_version(&CONFIG.downloader, "--version"),
+            // ffmpeg prints "ffmpeg version <version> Copyright ..."
+            ffmpeg: command_version("ffmpeg", "-version").and_then(|line| {
+                line.trim_start_matches("ffmpeg version ")
+                    .split_whitespace()
+                    .next()
+                    .map(String::from)
+            }),
+        }
+    }
+}
+
+/// Detect the versions of the external programs and log them
+/// The results are cached for the `/version` endpoint
+pub(crate) fn check() {
+    let versions = VERSIONS.read().expect("versions lock poisoned");
+    let downloader = CONFIG.downloader.display();
+    match &versions.downloader {
+        Some(detected) => info!("{} version: {}", downloader, detected),
+        None => warn!("{} not found, downloads will fail", downloader),
+    }
+    match &versions.ffmpeg {
+        Some(detected) => info!("ffmpeg version: {}", detected),
+        None => warn!("ffmpeg not found, merging formats and embedding subtitles will fail"),
+    }
+}
+
+/// Returns true if the downloader could be run when its version was detected
+pub(crate) fn downloader_available() -> bool {
+    VERSIONS
+        .read()
+        .expect("versions lock poisoned")
+        .downloader
+        .is_some()
+}
+
+/// Detect the downloader's version again, after it was updated
+pub(crate) fn refresh_downloader() -> Option<String> {
+    let detected = command_version(&CONFIG.downloader, "--version");
+    VERSIONS.write().expect("versions lock poisoned").downloader = detected.clone();
+    detected
+}
+
+/// Returns the first line of the program's version output
+fn command_version<P: AsRef<OsStr>>(program: P, arg: &str) -> Option<String> {
+    let output = Command::new(program).arg(arg).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout.lines().next()?.trim();
+    if line.is_empty() {
+        return None;
+    }
+
+    Some(line.to_string())
+}
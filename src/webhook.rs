@@ -0,0 +1,153 @@
+use actix_web::client::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::casing::Cased;
+use crate::config::CONFIG;
+use crate::jobs::{Job, JobStatus};
+
+/// Sent when no template is configured
+#[derive(Debug, Serialize)]
+struct Payload<'a> {
+    job: &'a Job,
+}
+
+/// A webhook body with placeholders, eg: {"text": "{{title}} {{status}}"}
+/// Placeholders: id, title, url, status, reason and location
+#[derive(Debug, Deserialize)]
+pub(crate) struct Template {
+    #[serde(default)]
+    content_type: ContentType,
+    body: String,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum ContentType {
+    /// The values are escaped as json strings
+    #[default]
+    Json,
+    /// The values are url encoded, eg: title={{title}}&url={{url}}
+    Form,
+}
+
+impl ContentType {
+    fn mime_type(self) -> &'static str {
+        match self {
+            ContentType::Json => "application/json",
+            ContentType::Form => "application/x-www-form-urlencoded",
+        }
+    }
+
+    fn escape(self, value: &str) -> String {
+        match self {
+            ContentType::Json => {
+                let quoted = serde_json::Value::from(value).to_string();
+                quoted[1..quoted.len() - 1].to_string()
+            }
+            ContentType::Form => url::form_urlencoded::byte_serialize(value.as_bytes()).collect(),
+        }
+    }
+}
+
+impl Template {
+    /// Fails if a json template doesn't render into json
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if let ContentType::Json = self.content_type {
+            let rendered = self.render(|_| Some(String::new()));
+            serde_json::from_str::<serde_json::Value>(&rendered)
+                .map_err(|e| format!("webhook_template is not valid json: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Replace the placeholders with their escaped value
+    /// Unknown placeholders are left as they are
+    fn render<F>(&self, value: F) -> String
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut rendered = String::with_capacity(self.body.len());
+        let mut rest = self.body.as_str();
+
+        // a single pass, so placeholders inside of the values aren't replaced
+        while let Some(start) = rest.find("{{") {
+            let end = match rest[start..].find("}}") {
+                Some(end) => start + end,
+                None => break,
+            };
+
+            rendered.push_str(&rest[..start]);
+            match value(rest[start + 2..end].trim()) {
+                Some(value) => rendered.push_str(&self.content_type.escape(&value)),
+                None => rendered.push_str(&rest[start..end + 2]),
+            }
+            rest = &rest[end + 2..];
+        }
+
+        rendered.push_str(rest);
+        rendered
+    }
+
+    fn render_job(&self, job: &Job) -> String {
+        self.render(|name| match name {
+            "id" => Some(job.id().to_string()),
+            "title" => Some(job.to_string()),
+            "url" => Some(job.url().to_string()),
+            "status" => Some(job.status().name().to_string()),
+            "reason" => match job.status() {
+                JobStatus::Failed(reason) => Some(reason.trim().to_string()),
+                _ => Some(String::new()),
+            },
+            "location" => Some(job.location().name().to_string()),
+            _ => None,
+        })
+    }
+}
+
+/// Send the completed job to the global webhook and the webhook of its location
+/// Each webhook is called once, failures are only logged
+pub(crate) fn notify(job: &Job) {
+    let mut urls: Vec<&str> = CONFIG.webhook_url.as_deref().into_iter().collect();
+    if let Some(url) = job.location().webhook_url() {
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+
+    if urls.is_empty() {
+        return;
+    }
+
+    let (content_type, body) = match &CONFIG.webhook_template {
+        Some(template) => (
+            template.content_type.mime_type(),
+            template.render_job(job).into_bytes(),
+        ),
+        None => match serde_json::to_vec(&Cased(Payload { job })) {
+            Ok(body) => ("application/json", body),
+            Err(e) => {
+                error!("unable to serialize the webhook payload: {}", e);
+                return;
+            }
+        },
+    };
+
+    for url in urls {
+        let url = url.to_string();
+        let body = body.clone();
+        actix::spawn(async move {
+            let res = Client::default()
+                .post(&url)
+                .content_type(content_type)
+                .send_body(body)
+                .await;
+            match res {
+                Ok(response) if response.status().is_success() => {
+                    debug!("called webhook {}", url);
+                }
+                Ok(response) => error!("webhook {} responded with {}", url, response.status()),
+                Err(e) => error!("unable to call webhook {}: {}", url, e),
+            }
+        });
+    }
+}
@@ -2,10 +2,14 @@ use std::time::{Duration, Instant};
 
 use actix::prelude::*;
 use actix_web::web::Data;
-use actix_web::{web, HttpRequest, Responder};
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
 
 use actix_web_actors::ws;
+use uuid::Uuid;
 
+use crate::casing::Cased;
+use crate::errors::YodelError;
 use crate::jobs;
 use crate::jobs::JobServer;
 
@@ -13,6 +17,46 @@ use crate::jobs::JobServer;
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 /// How long before lack of client response causes a timeout
 const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Version of the websocket message format, bump this when `JobResponse` changes
+const PROTOCOL_VERSION: u32 = 4;
+
+lazy_static::lazy_static! {
+    /// Changes on every start, clients that see a new one know their jobs and seq are stale
+    static ref INSTANCE_ID: Uuid = Uuid::new_v4();
+}
+
+/// Messages clients can send
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ClientMessage {
+    /// Resend all jobs as a snapshot
+    Refresh,
+}
+
+/// Sent when a client message can't be handled
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename = "error")]
+struct ErrorMessage {
+    message: String,
+    /// see `YodelError::code`
+    code: &'static str,
+}
+
+/// The first message sent to every client
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename = "hello", rename_all = "camelCase")]
+struct Hello {
+    protocol: u32,
+    server_version: &'static str,
+    instance_id: Uuid,
+}
+
+/// Sent by job scoped connections when the status or title of their job changes
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", content = "data", rename_all = "camelCase")]
+enum JobUpdate<'a> {
+    Job(&'a jobs::Job),
+}
 
 /// route used for game updates
 pub(crate) async fn route(
@@ -25,12 +69,88 @@ pub(crate) async fn route(
             id: 0,
             hb: Instant::now(),
             server: srv.get_ref().clone(),
+            watched: None,
         },
         &req,
         stream,
     )
 }
 
+/// Websocket that only receives the events of one job, it's closed once the job completes
+#[get("/jobs/{id}/ws")]
+pub(crate) async fn job_route(
+    req: HttpRequest,
+    id: web::Path<Uuid>,
+    stream: web::Payload,
+    srv: Data<Addr<JobServer>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let job = srv
+        .send(jobs::GetJob {
+            id: id.into_inner(),
+        })
+        .await
+        .map_err(YodelError::from)??;
+
+    ws::start(
+        WebsocketConnection {
+            id: 0,
+            hb: Instant::now(),
+            server: srv.get_ref().clone(),
+            watched: Some(WatchedJob { job }),
+        },
+        &req,
+        stream,
+    )
+}
+
+/// The job of a job scoped connection
+struct WatchedJob {
+    /// The latest version of the job
+    job: jobs::Job,
+}
+
+/// How a job scoped connection passes on a broadcast about its job
+enum Delivery {
+    /// Send the broadcast as it is
+    Broadcast,
+    /// Send the job as a `JobUpdate`, the rest of the snapshot is about other jobs
+    Update,
+}
+
+impl WatchedJob {
+    /// How to pass on the broadcast, nothing is sent when it's not about the job
+    /// Snapshots are only passed on when the job's status or title changed
+    fn update(&mut self, broadcast: &jobs::Broadcast) -> Option<Delivery> {
+        let id = self.job.id();
+        let (job, forward) = match broadcast.response() {
+            jobs::JobResponse::Progress(job)
+            | jobs::JobResponse::Finished(job)
+            | jobs::JobResponse::Failed { job, .. }
+            | jobs::JobResponse::Warning { job, .. }
+            | jobs::JobResponse::JobUpdated(job) => (job, true),
+            jobs::JobResponse::Snapshot { pending, completed } => {
+                match pending.iter().chain(completed).find(|job| job.id() == id) {
+                    Some(job) => (job, false),
+                    None => return None,
+                }
+            }
+        };
+        if job.id() != id {
+            return None;
+        }
+
+        let changed = job.status() != self.job.status() || job.title() != self.job.title();
+        self.job = job.clone();
+        if forward {
+            Some(Delivery::Broadcast)
+        } else if changed {
+            Some(Delivery::Update)
+        } else {
+            None
+        }
+    }
+}
+
 struct WebsocketConnection {
     /// unique session id
     /// Get's filled in when connecting
@@ -39,12 +159,22 @@ struct WebsocketConnection {
     /// otherwise we drop connection.
     hb: Instant,
     server: Addr<JobServer>,
+    /// Only the events of this job are sent
+    watched: Option<WatchedJob>,
 }
 
 impl Actor for WebsocketConnection {
     type Context = ws::WebsocketContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
+        let hello = Hello {
+            protocol: PROTOCOL_VERSION,
+            server_version: env!("CARGO_PKG_VERSION"),
+            instance_id: *INSTANCE_ID,
+        };
+        self.send_json(&hello, ctx);
+        self.announce(ctx);
+
         // we'll start heartbeat process on session start.
         self.hb(ctx);
         let addr = ctx.address();
@@ -74,11 +204,21 @@ impl Actor for WebsocketConnection {
 }
 
 /// Handle messages from server, we simply send it to peer websocket
-impl Handler<jobs::JobResponse> for WebsocketConnection {
+impl Handler<jobs::Broadcast> for WebsocketConnection {
     type Result = ();
 
-    fn handle(&mut self, notification: jobs::JobResponse, ctx: &mut Self::Context) {
-        ctx.text(serde_json::to_string(&notification).unwrap_or_default());
+    fn handle(&mut self, notification: jobs::Broadcast, ctx: &mut Self::Context) {
+        let watched = match &mut self.watched {
+            Some(watched) => watched,
+            None => return self.send_json(&notification, ctx),
+        };
+
+        match watched.update(&notification) {
+            Some(Delivery::Broadcast) => self.send_json(&notification, ctx),
+            Some(Delivery::Update) => self.announce(ctx),
+            None => return,
+        }
+        self.close_when_completed(ctx);
     }
 }
 
@@ -102,9 +242,13 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketConnecti
             ws::Message::Pong(_) => {
                 self.hb = Instant::now();
             }
-            ws::Message::Text(_) => {
-                debug!("ignoring incoming messages");
-            }
+            ws::Message::Text(text) => match serde_json::from_str::<ClientMessage>(&text) {
+                Ok(message) => self.handle_client_message(message, ctx),
+                Err(e) => {
+                    let error = YodelError::BadRequest(format!("Invalid message: {}", e));
+                    self.send_error(error, ctx);
+                }
+            },
             ws::Message::Binary(_) => debug!("Unexpected binary"),
             ws::Message::Close(reason) => {
                 ctx.close(reason);
@@ -119,6 +263,57 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketConnecti
 }
 
 impl WebsocketConnection {
+    fn handle_client_message(&self, message: ClientMessage, ctx: &mut ws::WebsocketContext<Self>) {
+        match message {
+            ClientMessage::Refresh if self.watched.is_some() => self.announce(ctx),
+            ClientMessage::Refresh => {
+                // the snapshot is sent like broadcasts are, with the seq they're ordered by
+                self.server.do_send(jobs::Refresh {
+                    addr: ctx.address().recipient(),
+                });
+            }
+        }
+    }
+
+    /// Send the watched job as it is now
+    fn announce(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        if let Some(watched) = &self.watched {
+            self.send_json(&JobUpdate::Job(&watched.job), ctx);
+        }
+        self.close_when_completed(ctx);
+    }
+
+    /// Close a job scoped connection once its job finished or failed
+    fn close_when_completed(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        let completed = self
+            .watched
+            .as_ref()
+            .is_some_and(|watched| watched.job.is_completed());
+        if completed {
+            ctx.close(Some(ws::CloseCode::Normal.into()));
+            ctx.stop();
+        }
+    }
+
+    fn send_error(&self, error: YodelError, ctx: &mut ws::WebsocketContext<Self>) {
+        let error = ErrorMessage {
+            message: error.to_string(),
+            code: error.code(),
+        };
+        self.send_json(&error, ctx);
+    }
+
+    /// Send the message as json, nothing is sent when it can't be serialized
+    fn send_json<T>(&self, message: &T, ctx: &mut ws::WebsocketContext<Self>)
+    where
+        T: Serialize + std::fmt::Debug,
+    {
+        match serde_json::to_string(&Cased(message)) {
+            Ok(text) => ctx.text(text),
+            Err(e) => error!("unable to serialize {:?}: {}", message, e),
+        }
+    }
+
     /// helper method that sends ping to client every second.
     ///
     /// also this method checks heartbeats from client